@@ -0,0 +1,356 @@
+use libp2p::PeerId;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+use tokio::time::{Duration, Instant};
+
+/// Window over which rates are measured
+const RATE_WINDOW: Duration = Duration::from_secs(5);
+
+/// Upload limits of seeder in bytes per sec.
+/// `None` for no limit.
+#[derive(Debug, Clone, Default)]
+pub struct BandwidthLimits {
+    pub global: Option<u64>,
+    pub per_peer: Option<u64>,
+    pub per_process: Option<u64>,
+}
+
+/// Upload rates in bytes per sec
+#[derive(Debug, Clone, Default)]
+pub struct BandwidthRates {
+    pub global: f64,
+    pub peers: HashMap<PeerId, f64>,
+    pub processes: HashMap<u32, f64>,
+}
+
+/// Token bucket of bytes holding at most a sec worth of
+/// `rate`. Tokens can go negative, so that chunks larger
+/// than the bucket can still be sent.
+#[derive(Debug)]
+struct ByteBucket {
+    rate: u64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl ByteBucket {
+    fn new(rate: u64, now: Instant) -> Self {
+        Self {
+            rate,
+            tokens: rate as f64,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.rate as f64).min(self.rate as f64);
+        self.last_refill = now;
+    }
+
+    /// Time till bucket has tokens again
+    fn wait(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        if self.tokens > 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / self.rate.max(1) as f64)
+        }
+    }
+
+    fn set_rate(&mut self, rate: u64, now: Instant) {
+        self.refill(now);
+        self.rate = rate;
+        self.tokens = self.tokens.min(rate as f64);
+    }
+}
+
+/// Bytes sent in the last `RATE_WINDOW`
+#[derive(Debug, Default)]
+struct RateMeter {
+    sent: VecDeque<(Instant, usize)>,
+}
+
+impl RateMeter {
+    fn record(&mut self, bytes: usize, now: Instant) {
+        self.sent.push_back((now, bytes));
+        self.expire(now);
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some((at, _)) = self.sent.front() {
+            if now.saturating_duration_since(*at) < RATE_WINDOW {
+                break;
+            }
+            self.sent.pop_front();
+        }
+    }
+
+    fn rate(&mut self, now: Instant) -> f64 {
+        self.expire(now);
+        self.sent.iter().map(|(_, b)| *b).sum::<usize>() as f64 / RATE_WINDOW.as_secs_f64()
+    }
+}
+
+struct ProcessShare {
+    peer_id: PeerId,
+    /// Share of global bandwidth relative
+    /// to other processes
+    weight: f64,
+    bucket: Option<ByteBucket>,
+    meter: RateMeter,
+}
+
+#[derive(Default)]
+struct Shaper {
+    limits: BandwidthLimits,
+    global: Option<ByteBucket>,
+    global_meter: RateMeter,
+    peers: HashMap<PeerId, (Option<ByteBucket>, RateMeter)>,
+    processes: HashMap<u32, ProcessShare>,
+}
+
+impl Shaper {
+    /// Splits global limit across processes in proportion
+    /// to their weights, capped by the per process limit
+    fn reshare(&mut self, now: Instant) {
+        let total_weight: f64 = self.processes.values().map(|p| p.weight).sum();
+        for share in self.processes.values_mut() {
+            let global_share = self
+                .limits
+                .global
+                .map(|g| (g as f64 * share.weight / total_weight) as u64);
+            let rate = match (global_share, self.limits.per_process) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+            share.bucket = match (rate, share.bucket.take()) {
+                (Some(rate), Some(mut bucket)) => {
+                    bucket.set_rate(rate, now);
+                    Some(bucket)
+                }
+                (Some(rate), None) => Some(ByteBucket::new(rate, now)),
+                (None, _) => None,
+            };
+        }
+    }
+}
+
+/// Shapes upload bandwidth of seeder. Processes get
+/// a share of global bandwidth in proportion to their
+/// priority. Shared with the application for
+/// updating limits and monitoring rates.
+#[derive(Clone, Default)]
+pub struct BandwidthShaper {
+    inner: Arc<Mutex<Shaper>>,
+}
+
+impl BandwidthShaper {
+    pub fn new(limits: BandwidthLimits) -> Self {
+        let shaper = Self::default();
+        shaper.set_limits(limits);
+        shaper
+    }
+
+    pub fn set_limits(&self, limits: BandwidthLimits) {
+        let now = Instant::now();
+        let mut inner = self.inner.lock().unwrap();
+        inner.global = limits.global.map(|rate| ByteBucket::new(rate, now));
+        for (bucket, _) in inner.peers.values_mut() {
+            *bucket = limits.per_peer.map(|rate| ByteBucket::new(rate, now));
+        }
+        inner.limits = limits;
+        inner.reshare(now);
+    }
+
+    pub fn has_process(&self, id: u32) -> bool {
+        self.inner.lock().unwrap().processes.contains_key(&id)
+    }
+
+    /// Adds process `id` with requester `peer_id`.
+    /// Higher `weight` gets higher share.
+    pub fn add_process(&self, id: u32, peer_id: PeerId, weight: f64) {
+        let now = Instant::now();
+        let mut inner = self.inner.lock().unwrap();
+        let per_peer = inner.limits.per_peer;
+        inner.peers.entry(peer_id).or_insert_with(|| {
+            (
+                per_peer.map(|rate| ByteBucket::new(rate, now)),
+                RateMeter::default(),
+            )
+        });
+        inner.processes.insert(
+            id,
+            ProcessShare {
+                peer_id,
+                weight: weight.max(f64::MIN_POSITIVE),
+                bucket: None,
+                meter: RateMeter::default(),
+            },
+        );
+        inner.reshare(now);
+    }
+
+    /// Updates `weight` of process `id`, e.g. once
+    /// reputation of its requester changes
+    pub fn set_weight(&self, id: u32, weight: f64) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(share) = inner.processes.get_mut(&id) {
+            share.weight = weight.max(f64::MIN_POSITIVE);
+            inner.reshare(Instant::now());
+        }
+    }
+
+    pub fn remove_process(&self, id: u32) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(share) = inner.processes.remove(&id) {
+            if !inner.processes.values().any(|p| p.peer_id == share.peer_id) {
+                inner.peers.remove(&share.peer_id);
+            }
+        }
+        inner.reshare(Instant::now());
+    }
+
+    /// Takes `bytes` for sending by process `id`. If any
+    /// limit is exhausted returns time to wait before
+    /// trying again.
+    pub fn acquire(&self, id: u32, bytes: usize, now: Instant) -> Result<(), Duration> {
+        let mut inner = self.inner.lock().unwrap();
+        let Shaper {
+            global,
+            global_meter,
+            peers,
+            processes,
+            ..
+        } = &mut *inner;
+        let share = match processes.get_mut(&id) {
+            Some(share) => share,
+            None => return Ok(()),
+        };
+        let peer = peers.get_mut(&share.peer_id);
+
+        let mut buckets: Vec<&mut ByteBucket> = Vec::new();
+        buckets.extend(global.as_mut());
+        buckets.extend(share.bucket.as_mut());
+        if let Some((Some(bucket), _)) = peer {
+            buckets.push(bucket);
+        }
+        let wait = buckets
+            .iter_mut()
+            .map(|b| b.wait(now))
+            .max()
+            .unwrap_or_default();
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        for bucket in buckets {
+            bucket.tokens -= bytes as f64;
+        }
+
+        global_meter.record(bytes, now);
+        share.meter.record(bytes, now);
+        if let Some((_, meter)) = peers.get_mut(&share.peer_id) {
+            meter.record(bytes, now);
+        }
+        Ok(())
+    }
+
+    /// Current upload rates
+    pub fn rates(&self) -> BandwidthRates {
+        let now = Instant::now();
+        let mut inner = self.inner.lock().unwrap();
+        BandwidthRates {
+            global: inner.global_meter.rate(now),
+            peers: inner
+                .peers
+                .iter_mut()
+                .map(|(peer_id, (_, meter))| (*peer_id, meter.rate(now)))
+                .collect(),
+            processes: inner
+                .processes
+                .iter_mut()
+                .map(|(id, share)| (*id, share.meter.rate(now)))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn global_bandwidth_is_shared_by_priority() {
+        let shaper = BandwidthShaper::new(BandwidthLimits {
+            global: Some(1000),
+            ..Default::default()
+        });
+        shaper.add_process(0, PeerId::random(), 3.0);
+        shaper.add_process(1, PeerId::random(), 1.0);
+        let now = Instant::now();
+
+        // a sec worth of each share can be sent at once
+        assert!(shaper.acquire(0, 750, now).is_ok());
+        assert!(shaper.acquire(0, 1, now).is_err());
+        assert!(shaper.acquire(1, 250, now).is_ok());
+        let wait = shaper.acquire(1, 1, now).unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_secs(1));
+
+        // remaining process gets the whole share
+        shaper.remove_process(0);
+        let later = now + Duration::from_secs(1);
+        assert!(shaper.acquire(1, 1000, later).is_ok());
+
+        let rates = shaper.rates();
+        assert_eq!(rates.processes.len(), 1);
+        assert_eq!(rates.global, 2000.0 / RATE_WINDOW.as_secs_f64());
+    }
+
+    #[test]
+    fn shares_follow_updated_weights() {
+        let shaper = BandwidthShaper::new(BandwidthLimits {
+            global: Some(1000),
+            ..Default::default()
+        });
+        shaper.add_process(0, PeerId::random(), 3.0);
+        shaper.add_process(1, PeerId::random(), 1.0);
+        shaper.set_weight(1, 3.0);
+
+        // buckets refill at the updated rates
+        let later = Instant::now() + Duration::from_secs(1);
+        for id in [0, 1] {
+            assert!(shaper.acquire(id, 500, later).is_ok());
+            assert!(shaper.acquire(id, 1, later).is_err());
+        }
+    }
+
+    #[test]
+    fn peer_and_process_limits_cap_shares() {
+        let shaper = BandwidthShaper::new(BandwidthLimits {
+            global: None,
+            per_peer: Some(600),
+            per_process: Some(400),
+        });
+        let (peer, other) = (PeerId::random(), PeerId::random());
+        shaper.add_process(0, peer, 1.0);
+        shaper.add_process(1, peer, 1.0);
+        shaper.add_process(2, other, 1.0);
+        let now = Instant::now();
+
+        // process limit
+        assert!(shaper.acquire(0, 400, now).is_ok());
+        assert!(shaper.acquire(0, 1, now).is_err());
+        // peer limit is shared by processes of the peer
+        assert!(shaper.acquire(1, 200, now).is_ok());
+        assert!(shaper.acquire(1, 1, now).is_err());
+        // other peers aren't affected
+        assert!(shaper.acquire(2, 400, now).is_ok());
+
+        let rates = shaper.rates();
+        assert_eq!(rates.peers[&peer], 600.0 / RATE_WINDOW.as_secs_f64());
+        assert_eq!(rates.processes[&1], 200.0 / RATE_WINDOW.as_secs_f64());
+    }
+}
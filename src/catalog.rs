@@ -0,0 +1,368 @@
+use super::network::chunk_stream::MAX_CHUNK_SIZE;
+use super::storage::Storage;
+use ethers::{
+    types::{H256, U256},
+    utils::keccak256,
+};
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// Metadata of a file registered in the
+/// catalog. Persisted in `Storage`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileMetadata {
+    /// Merkle root of chunk hashes. Files
+    /// are identified by it.
+    pub root_hash: H256,
+    /// Size of file in bytes
+    pub size: usize,
+    pub chunk_size: usize,
+    pub chunk_price: U256,
+    pub path: PathBuf,
+}
+
+/// File on disk. Chunks are read with positioned
+/// reads, thus file is never loaded in memory
+/// as a whole.
+pub struct File {
+    pub metadata: FileMetadata,
+    handle: fs::File,
+    /// Levels of merkle tree, from chunk
+    /// hashes up to the root hash
+    tree: Vec<Vec<[u8; 32]>>,
+}
+
+impl File {
+    /// Opens file at `path` and computes its
+    /// metadata by streaming it chunk by chunk.
+    pub fn open(path: &Path, chunk_size: usize, chunk_price: U256) -> anyhow::Result<Self> {
+        if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+            return Err(anyhow::anyhow!(
+                "Chunk size should be non zero and at most {}",
+                MAX_CHUNK_SIZE
+            ));
+        }
+
+        let handle = fs::File::open(path)?;
+        let mut reader = io::BufReader::new(&handle);
+        let mut buf = vec![0u8; chunk_size];
+        let mut leaves = Vec::new();
+        let mut size = 0;
+        loop {
+            let n = read_full(&mut reader, &mut buf)?;
+            if n == 0 {
+                break;
+            }
+            leaves.push(keccak256(&buf[..n]));
+            size += n;
+        }
+
+        let tree = merkle_tree(leaves);
+        Ok(Self {
+            metadata: FileMetadata {
+                root_hash: H256::from(tree[tree.len() - 1][0]),
+                size,
+                chunk_size,
+                chunk_price,
+                path: path.to_path_buf(),
+            },
+            handle,
+            tree,
+        })
+    }
+
+    /// Reads chunk at `index` from disk. Returns
+    /// `None` if `index` is out of range.
+    pub fn read_chunk_at_index(&self, index: usize) -> io::Result<Option<Vec<u8>>> {
+        if index >= self.chunk_count() {
+            return Ok(None);
+        }
+
+        let offset = index * self.metadata.chunk_size;
+        let len = self.metadata.chunk_size.min(self.metadata.size - offset);
+        let mut chunk = vec![0u8; len];
+        self.handle.read_exact_at(&mut chunk, offset as u64)?;
+        Ok(Some(chunk))
+    }
+
+    /// Merkle proof of chunk at `index`, i.e. siblings
+    /// of the nodes on its path to the root. Returns
+    /// `None` if `index` is out of range.
+    pub fn proof(&self, index: usize) -> Option<Vec<[u8; 32]>> {
+        if index >= self.chunk_count() {
+            return None;
+        }
+
+        let mut index = index;
+        let mut proof = Vec::new();
+        for level in &self.tree[..self.tree.len() - 1] {
+            proof.push(*level.get(index ^ 1).unwrap_or(&level[index]));
+            index /= 2;
+        }
+        Some(proof)
+    }
+
+    /// Total no. of chunks in the file
+    pub fn chunk_count(&self) -> usize {
+        self.metadata.size.div_ceil(self.metadata.chunk_size)
+    }
+}
+
+/// Reads from `reader` till `buf` is full or
+/// EOF is reached. Returns no. of bytes read.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Computes merkle root over `leaves` (keccak256
+/// hashes of chunks). Last node of a level is paired
+/// with itself when the level has odd no. of nodes.
+pub fn merkle_root(leaves: Vec<[u8; 32]>) -> H256 {
+    let tree = merkle_tree(leaves);
+    H256::from(tree[tree.len() - 1][0])
+}
+
+/// Levels of merkle tree over `leaves`. Last
+/// level holds the root alone.
+fn merkle_tree(leaves: Vec<[u8; 32]>) -> Vec<Vec<[u8; 32]>> {
+    if leaves.is_empty() {
+        return vec![vec![keccak256([])]];
+    }
+
+    let mut tree = vec![leaves];
+    while tree[tree.len() - 1].len() > 1 {
+        let level = tree[tree.len() - 1]
+            .chunks(2)
+            .map(|pair| {
+                let right = pair.get(1).unwrap_or(&pair[0]);
+                keccak256([pair[0], *right].concat())
+            })
+            .collect();
+        tree.push(level);
+    }
+    tree
+}
+
+/// Verifies `chunk` at `index` of file with `root_hash`
+/// and `chunk_count` chunks against its merkle `proof`
+pub fn verify_chunk(
+    root_hash: &H256,
+    chunk_count: usize,
+    index: usize,
+    chunk: &[u8],
+    proof: &[[u8; 32]],
+) -> bool {
+    // proof should span all levels of the tree
+    let mut depth = 0;
+    let mut nodes = chunk_count;
+    while nodes > 1 {
+        nodes = nodes.div_ceil(2);
+        depth += 1;
+    }
+    if index >= chunk_count || proof.len() != depth {
+        return false;
+    }
+
+    let mut index = index;
+    let mut node = keccak256(chunk);
+    for sibling in proof {
+        node = if index.is_multiple_of(2) {
+            keccak256([node, *sibling].concat())
+        } else {
+            keccak256([*sibling, node].concat())
+        };
+        index /= 2;
+    }
+    H256::from(node) == *root_hash
+}
+
+/// Catalog of files served by the seeder.
+///
+/// Catalog is cheap to clone, thus a handle
+/// can be kept around to add/remove files
+/// while the seeder is running.
+#[derive(Clone)]
+pub struct Catalog {
+    storage: Storage,
+    files: Arc<Mutex<HashMap<H256, Arc<File>>>>,
+}
+
+impl Catalog {
+    /// Loads all files whose metadata is
+    /// persisted in `storage`
+    pub fn new(storage: Storage) -> Self {
+        let mut files = HashMap::new();
+        for (root_hash, metadata) in storage.get_all_file_metadata().unwrap_or_default() {
+            match File::open(&metadata.path, metadata.chunk_size, metadata.chunk_price) {
+                Ok(file) if file.metadata.root_hash == root_hash => {
+                    files.insert(root_hash, Arc::new(file));
+                }
+                Ok(_) => {
+                    error!(
+                        "(catalog) file at {:?} changed since it was registered",
+                        metadata.path
+                    );
+                }
+                Err(e) => {
+                    error!(
+                        "(catalog) failed to load file at {:?} with error {}",
+                        metadata.path, e
+                    );
+                }
+            }
+        }
+
+        Self {
+            storage,
+            files: Arc::new(Mutex::new(files)),
+        }
+    }
+
+    /// Registers all files (non-recursively) in `dir`
+    /// and returns their root hashes
+    pub fn register_dir(
+        &self,
+        dir: &Path,
+        chunk_size: usize,
+        chunk_price: U256,
+    ) -> anyhow::Result<Vec<H256>> {
+        let mut root_hashes = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_file() {
+                root_hashes.push(self.add_file(&path, chunk_size, chunk_price)?);
+            }
+        }
+        Ok(root_hashes)
+    }
+
+    /// Adds file at `path` to the catalog
+    /// and returns its root hash
+    pub fn add_file(
+        &self,
+        path: &Path,
+        chunk_size: usize,
+        chunk_price: U256,
+    ) -> anyhow::Result<H256> {
+        let file = File::open(path, chunk_size, chunk_price)?;
+        let root_hash = file.metadata.root_hash;
+        self.storage.add_file_metadata(&file.metadata)?;
+        self.files.lock().unwrap().insert(root_hash, Arc::new(file));
+
+        debug!("(catalog) added file {:?} with root {:?}", path, root_hash);
+        Ok(root_hash)
+    }
+
+    /// Removes file from the catalog
+    pub fn remove_file(&self, root_hash: &H256) -> anyhow::Result<()> {
+        self.storage.remove_file_metadata(root_hash)?;
+        self.files.lock().unwrap().remove(root_hash);
+        Ok(())
+    }
+
+    pub fn get(&self, root_hash: &H256) -> Option<Arc<File>> {
+        self.files.lock().unwrap().get(root_hash).cloned()
+    }
+
+    pub fn root_hashes(&self) -> Vec<H256> {
+        self.files.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dse-catalog-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn last_chunk_is_partial() {
+        let dir = dir("partial");
+        fs::write(dir.join("file"), b"0123456789").unwrap();
+        let file = File::open(&dir.join("file"), 4, U256::one()).unwrap();
+
+        assert_eq!(file.metadata.size, 10);
+        assert_eq!(file.chunk_count(), 3);
+        assert_eq!(file.read_chunk_at_index(2).unwrap().unwrap(), b"89");
+        assert_eq!(file.read_chunk_at_index(3).unwrap(), None);
+        assert_eq!(
+            file.metadata.root_hash,
+            merkle_root(vec![
+                keccak256(b"0123"),
+                keccak256(b"4567"),
+                keccak256(b"89")
+            ])
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn chunks_are_verified_with_proofs() {
+        let dir = dir("proofs");
+        fs::write(dir.join("file"), b"0123456789").unwrap();
+        let file = File::open(&dir.join("file"), 2, U256::one()).unwrap();
+        let root_hash = file.metadata.root_hash;
+
+        for index in 0..file.chunk_count() {
+            let chunk = file.read_chunk_at_index(index).unwrap().unwrap();
+            let proof = file.proof(index).unwrap();
+            assert!(verify_chunk(&root_hash, 5, index, &chunk, &proof));
+            assert!(!verify_chunk(&root_hash, 5, index, b"xx", &proof));
+            assert!(!verify_chunk(&root_hash, 5, index ^ 1, &chunk, &proof));
+        }
+        assert_eq!(file.proof(5), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn chunk_size_is_bounded() {
+        let dir = dir("chunk-size");
+        fs::write(dir.join("file"), b"0123456789").unwrap();
+        for chunk_size in [0, MAX_CHUNK_SIZE + 1] {
+            assert!(File::open(&dir.join("file"), chunk_size, U256::one()).is_err());
+        }
+        assert!(File::open(&dir.join("file"), MAX_CHUNK_SIZE, U256::one()).is_ok());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn files_are_restored_unless_changed() {
+        let dir = dir("restore");
+        let storage = Storage::open(&dir.join("dbs"));
+        let catalog = Catalog::new(storage.clone());
+        fs::write(dir.join("kept"), b"kept").unwrap();
+        fs::write(dir.join("changed"), b"changed").unwrap();
+        let kept = catalog.add_file(&dir.join("kept"), 2, U256::one()).unwrap();
+        let changed = catalog
+            .add_file(&dir.join("changed"), 2, U256::one())
+            .unwrap();
+
+        fs::write(dir.join("changed"), b"CHANGED").unwrap();
+        let catalog = Catalog::new(storage);
+        assert!(catalog.get(&kept).is_some());
+        assert!(catalog.get(&changed).is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
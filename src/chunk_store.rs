@@ -0,0 +1,137 @@
+use super::catalog::Catalog;
+use ethers::types::H256;
+use lru::LruCache;
+use std::sync::{Arc, Mutex};
+
+type ChunkCache = LruCache<(H256, usize), Arc<Vec<u8>>>;
+
+/// Serves chunks of files in the catalog.
+///
+/// Chunks are read from disk on demand and
+/// recently read chunks are kept in an LRU
+/// cache, since chunks of the same file are
+/// usually requested by many requesters.
+#[derive(Clone)]
+pub struct ChunkStore {
+    catalog: Catalog,
+    cache: Arc<Mutex<ChunkCache>>,
+}
+
+impl ChunkStore {
+    /// `cache_capacity` is the max no. of
+    /// chunks kept in cache. At least one
+    /// chunk is always kept.
+    pub fn new(catalog: Catalog, cache_capacity: usize) -> Self {
+        Self {
+            catalog,
+            cache: Arc::new(Mutex::new(LruCache::new(cache_capacity.max(1)))),
+        }
+    }
+
+    /// Returns chunk at `index` of file with `root_hash`.
+    /// Returns `None` if file isn't in the catalog or
+    /// `index` is out of range.
+    pub fn read_chunk(
+        &self,
+        root_hash: &H256,
+        index: usize,
+    ) -> anyhow::Result<Option<Arc<Vec<u8>>>> {
+        // file might have been removed from the catalog
+        // after its chunks were cached
+        let file = match self.catalog.get(root_hash) {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+
+        if let Some(chunk) = self.cache.lock().unwrap().get(&(*root_hash, index)) {
+            return Ok(Some(chunk.clone()));
+        }
+
+        // read outside the lock, so that a slow disk read
+        // does not block cache hits for other chunks
+        match file.read_chunk_at_index(index)? {
+            Some(chunk) => {
+                let chunk = Arc::new(chunk);
+                self.cache
+                    .lock()
+                    .unwrap()
+                    .put((*root_hash, index), chunk.clone());
+                Ok(Some(chunk))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Storage;
+    use ethers::types::U256;
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    fn dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("dse-chunk-store-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Store serving a file of 2.5 chunks of 4 bytes
+    fn store(dir: &Path, cache_capacity: usize) -> (ChunkStore, H256) {
+        let catalog = Catalog::new(Storage::open(&dir.join("dbs")));
+        fs::write(dir.join("file"), b"0123456789").unwrap();
+        let root_hash = catalog.add_file(&dir.join("file"), 4, U256::one()).unwrap();
+        (ChunkStore::new(catalog, cache_capacity), root_hash)
+    }
+
+    fn read(store: &ChunkStore, root_hash: &H256, index: usize) -> Option<Vec<u8>> {
+        store
+            .read_chunk(root_hash, index)
+            .unwrap()
+            .map(|c| c.to_vec())
+    }
+
+    #[test]
+    fn chunks_are_served_from_cache() {
+        let dir = dir("cache");
+        let (store, root_hash) = store(&dir, 2);
+        assert_eq!(read(&store, &root_hash, 0).unwrap(), b"0123");
+
+        // cached chunk is served as it was read,
+        // others are read from disk
+        fs::write(dir.join("file"), b"abcdefghij").unwrap();
+        assert_eq!(read(&store, &root_hash, 0).unwrap(), b"0123");
+        assert_eq!(read(&store, &root_hash, 2).unwrap(), b"ij");
+        assert_eq!(read(&store, &root_hash, 3), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn zero_capacity_keeps_one_chunk() {
+        let dir = dir("zero");
+        let (store, root_hash) = store(&dir, 0);
+        assert_eq!(read(&store, &root_hash, 0).unwrap(), b"0123");
+        assert_eq!(read(&store, &root_hash, 1).unwrap(), b"4567");
+
+        // chunk 0 was evicted by chunk 1
+        fs::write(dir.join("file"), b"abcdefghij").unwrap();
+        assert_eq!(read(&store, &root_hash, 1).unwrap(), b"4567");
+        assert_eq!(read(&store, &root_hash, 0).unwrap(), b"abcd");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn removed_files_are_not_served() {
+        let dir = dir("removed");
+        let (store, root_hash) = store(&dir, 2);
+        assert!(read(&store, &root_hash, 0).is_some());
+        store.catalog.remove_file(&root_hash).unwrap();
+        assert_eq!(read(&store, &root_hash, 0), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
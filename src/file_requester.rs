@@ -1,16 +1,1562 @@
-use ethers::types::{Address, U256};
+use super::catalog::{verify_chunk, File};
+use super::flow_control::MAX_WINDOW;
+use super::network::chunk_stream::{Chunk, MAX_CHUNK_SIZE};
+use super::network::file_exchange::{
+    FileExchangeRequest, FileExchangeResponse, PaymentSchedule, QuoteTerms,
+};
+use super::network::peer_table::PeerTable;
+use super::network::{
+    dial, expect_chunks, get_providers, send_file_request, send_file_request_detached,
+    send_file_response, Command, Network, NetworkEvent,
+};
+use super::pricing::{QuoteDecision, RequesterPricingPolicy};
+use super::process_state::ProcessState;
+use super::reputation::{ReputationEvent, Reputations};
+use super::storage::Storage;
+use super::wallet::{Attestation, ReceiptWithSignatures, Wallet};
+use ethers::types::{Address, H256, U256};
+use libp2p::{Multiaddr, PeerId};
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fs::{self, OpenOptions},
+    ops::Range,
+    os::unix::fs::FileExt,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    select,
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc,
+    },
+    time::{self, Duration, Instant},
+};
+
+/// No. of chunks after the next expected chunk that are
+/// left with a seeder when its range is shrunk, since they
+/// might already be in flight.
+const RANGE_SHRINK_MARGIN: usize = 2;
+
+/// No. of chunks received in order after
+/// which they are acked
+const ACK_EVERY: usize = 4;
+
+/// Max time chunks are left unacked
+const ACK_DELAY: Duration = Duration::from_millis(100);
+
+/// Time after which a file request that
+/// seeder hasn't accepted is dropped
+const PENDING_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Process object for tracking file being received
+#[derive(Serialize, Deserialize, Clone)]
 pub struct RProcess {
     pub id: u32,
+    /// Root hash of file being received
+    pub root_hash: H256,
     pub sender_address: Address,
-    /// Index of latest chunk of data
-    /// received
+    pub seeder_peer_id: PeerId,
+    /// Address seeder was dialed on. Used for
+    /// re-establishing connection on restart.
+    seeder_multiaddr: Option<Multiaddr>,
+    chunk_size: usize,
+    chunk_price: U256,
+    payment_schedule: PaymentSchedule,
+    /// Size of file in bytes
+    size: usize,
+    /// Index of the next chunk expected
     sequence_no: usize,
-    /// `rfp_sequence_no` should only
-    /// be 1 less than `sequence_no`
+    /// Index of chunk (exclusive) at
+    /// which the process ends
+    end_sequence_no: usize,
+    /// Index of the first chunk that
+    /// hasn't been paid for
     rfp_sequence_no: usize,
+    /// Latest receipt signed for seeder
+    last_receipt: Option<ReceiptWithSignatures>,
+    state: ProcessState,
+}
+
+impl RProcess {
+    /// No. of chunks left to receive
+    fn remaining(&self) -> usize {
+        self.end_sequence_no.saturating_sub(self.sequence_no)
+    }
+
+    /// No. of chunks left to receive that
+    /// haven't been paid for in advance
+    fn unpaid_remaining(&self) -> usize {
+        self.end_sequence_no
+            .saturating_sub(self.sequence_no.max(self.rfp_sequence_no))
+    }
+
+    /// Whether all chunks have been received and paid for
+    fn is_complete(&self) -> bool {
+        self.sequence_no >= self.end_sequence_no && self.rfp_sequence_no >= self.end_sequence_no
+    }
+
+    pub fn state(&self) -> &ProcessState {
+        &self.state
+    }
+}
+
+/// Download of a file from one or more seeders.
+/// Each seeder sends a disjoint range of chunks
+/// through its own process, and all of them are
+/// written to the same file.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Download {
+    pub root_hash: H256,
+    chunk_size: usize,
+    /// Size of file in bytes
+    size: usize,
+    /// Seeders the file is downloaded from,
+    /// along with addresses to dial them on
+    seeders: Vec<(PeerId, Option<Multiaddr>)>,
+}
+
+impl Download {
+    fn chunk_count(&self) -> usize {
+        self.size.div_ceil(self.chunk_size)
+    }
+}
+
+/// Download being written to. File is kept open till the
+/// download ends. Chunks received are tracked in a bitmap,
+/// whose words are stored one at a time as they change.
+struct OpenDownload {
+    file: fs::File,
+    received: Vec<u64>,
+    /// No. of chunks yet to be received
+    remaining: usize,
+}
+
+impl OpenDownload {
+    /// Opens download of `chunk_count` chunks written to `file`,
+    /// with `received` words of bitmap keyed by their index
+    fn new(file: fs::File, chunk_count: usize, received: HashMap<u32, u64>) -> Self {
+        let mut words = vec![0; chunk_count.div_ceil(64)];
+        for (index, word) in received {
+            if let Some(w) = words.get_mut(index as usize) {
+                *w = word;
+            }
+        }
+        let count: usize = words.iter().map(|w| w.count_ones() as usize).sum();
+        Self {
+            file,
+            received: words,
+            remaining: chunk_count.saturating_sub(count),
+        }
+    }
+
+    fn is_received(&self, index: usize) -> bool {
+        self.received[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    /// Marks chunk at `index` received. Returns index
+    /// and value of the word of bitmap that changed.
+    fn set_received(&mut self, index: usize) -> (u32, u64) {
+        if !self.is_received(index) {
+            self.received[index / 64] |= 1 << (index % 64);
+            self.remaining -= 1;
+        }
+        ((index / 64) as u32, self.received[index / 64])
+    }
+}
+
+/// Quote accepted by requester
+struct AcceptedQuote {
+    quote_id: u32,
+    range: Range<usize>,
+    /// Size of file in bytes
+    size: usize,
+    terms: QuoteTerms,
+}
+
+/// File request sent to a seeder
+/// that hasn't been accepted yet
+struct PendingRequest {
+    seeder_multiaddr: Option<Multiaddr>,
+    range: Option<Range<usize>>,
+    sent_at: Instant,
+    /// No. of counter offers made
+    counters: usize,
+    accepted: Option<AcceptedQuote>,
+}
+
+/// Request sent to seeder
+enum SentRequest {
+    /// `IWant` or `CounterOffer`, answered with a quote
+    Quote {
+        root_hash: H256,
+    },
+    Accept {
+        root_hash: H256,
+    },
+    /// Range update of process. File is requested
+    /// from `then` seeders once seeder acks it.
+    UpdateRange {
+        process_id: u32,
+        root_hash: H256,
+        end_sequence_no: usize,
+        then: Vec<PeerId>,
+    },
+}
+
+/// Seeder's response to a request
+struct SeederResponse {
+    seeder_peer_id: PeerId,
+    request: SentRequest,
+    response: anyhow::Result<FileExchangeResponse>,
 }
 
-struct FileRequester {
-    // When seeder accepts the request
-// start the process
+/// Transfer stats of a seeder used for
+/// rebalancing ranges across seeders
+#[derive(Default)]
+struct SeederStats {
+    bytes: usize,
+    elapsed: Duration,
+    last_chunk_at: Option<Instant>,
+    chunk_price: U256,
+}
+
+impl SeederStats {
+    /// Bytes received per second
+    fn rate(&self) -> Option<f64> {
+        if self.elapsed.is_zero() {
+            None
+        } else {
+            Some(self.bytes as f64 / self.elapsed.as_secs_f64())
+        }
+    }
+
+    /// Bytes per second per unit of price paid for a chunk.
+    /// Seeders without stats score highest, so that they
+    /// are tried out.
+    fn score(&self) -> f64 {
+        match self.rate() {
+            Some(rate) => rate / (self.chunk_price.low_u128() as f64 + 1.0),
+            None => f64::MAX,
+        }
+    }
+}
+
+pub struct FileRequester {
+    storage: Storage,
+    wallet: Wallet,
+    reputations: Reputations,
+    pricing_policy: Box<dyn RequesterPricingPolicy>,
+    /// Binds our peer id to our address
+    attestation: Attestation,
+    /// Directory received files are written to
+    download_dir: PathBuf,
+    /// Files requested from seeders that haven't
+    /// accepted yet
+    pending_requests: HashMap<(PeerId, H256), PendingRequest>,
+    /// Seeders to spread download across once the first
+    /// seeder accepts and file size is known
+    pending_downloads: HashMap<H256, Vec<(PeerId, Option<Multiaddr>)>>,
+    /// Cumulative acks of processes not sent yet,
+    /// along with no. of chunks they ack
+    pending_acks: HashMap<(PeerId, u32), (usize, usize)>,
+    /// Processes whose chunks are accepted by the network
+    expected_chunks: HashSet<(PeerId, u32)>,
+    /// Processes with a range update in flight
+    shrinking: HashSet<(PeerId, u32)>,
+    /// Downloads chunks have been written to
+    open_downloads: HashMap<H256, OpenDownload>,
+    /// Responses to requests sent to seeders are awaited
+    /// outside of the event loop, so that chunks keep
+    /// being received and acked meanwhile.
+    response_sender: mpsc::UnboundedSender<SeederResponse>,
+    response_receiver: mpsc::UnboundedReceiver<SeederResponse>,
+    seeder_stats: HashMap<PeerId, SeederStats>,
+    /// Round trip times to seeders
+    peer_table: PeerTable,
+    network_event_receiver: broadcast::Receiver<NetworkEvent>,
+    network_command_sender: mpsc::Sender<Command>,
+}
+
+impl FileRequester {
+    /// Downloads files through `network` into `download_dir`
+    pub fn new(
+        storage: Storage,
+        wallet: Wallet,
+        pricing_policy: Box<dyn RequesterPricingPolicy>,
+        download_dir: PathBuf,
+        network: &Network,
+    ) -> Self {
+        let attestation = wallet.attest(network.keypair.public().to_peer_id());
+        let (response_sender, response_receiver) = mpsc::unbounded_channel();
+        Self {
+            reputations: Reputations::new(storage.clone(), network.ban_list()),
+            storage,
+            wallet,
+            pricing_policy,
+            attestation,
+            download_dir,
+            pending_requests: HashMap::new(),
+            pending_downloads: HashMap::new(),
+            pending_acks: HashMap::new(),
+            expected_chunks: HashSet::new(),
+            shrinking: HashSet::new(),
+            open_downloads: HashMap::new(),
+            response_sender,
+            response_receiver,
+            seeder_stats: HashMap::new(),
+            peer_table: network.peer_table(),
+            network_event_receiver: network.network_event_receiver(),
+            network_command_sender: network.network_command_sender(),
+        }
+    }
+
+    fn file_path(&self, root_hash: &H256) -> PathBuf {
+        self.download_dir.join(format!("{:x}", root_hash))
+    }
+
+    /// Returns download of file with `root_hash`, opening
+    /// its file and loading chunks received if not open
+    fn open_download(&mut self, root_hash: &H256) -> anyhow::Result<&mut OpenDownload> {
+        let path = self.file_path(root_hash);
+        match self.open_downloads.entry(*root_hash) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => {
+                let download = self
+                    .storage
+                    .find_active_download(root_hash)?
+                    .ok_or_else(|| anyhow::anyhow!("Download {:?} does not exists", root_hash))?;
+                let file = OpenOptions::new().write(true).open(path)?;
+                Ok(entry.insert(OpenDownload::new(
+                    file,
+                    download.chunk_count(),
+                    self.storage.get_received_chunks(root_hash)?,
+                )))
+            }
+        }
+    }
+
+    /// Sorts `seeders` from the most to the least preferred.
+    /// Faster and cheaper seeders are preferred, and seeders
+    /// with same score (e.g. ones without stats) are ordered
+    /// by round trip time.
+    fn rank_seeders(&self, seeders: &mut [(PeerId, Option<Multiaddr>)]) {
+        let score = |p: &PeerId| self.seeder_stats.get(p).map_or(f64::MAX, |s| s.score());
+        let rtt = |p: &PeerId| self.peer_table.rtt(p).unwrap_or(Duration::MAX);
+        seeders.sort_by(|(a, _), (b, _)| {
+            score(b)
+                .total_cmp(&score(a))
+                .then_with(|| rtt(a).cmp(&rtt(b)))
+        });
+    }
+
+    /// Downloads file with `root_hash` from `seeders`.
+    ///
+    /// Whole file is requested from the most preferred seeder.
+    /// Once it accepts, and thus file size is known, chunks are
+    /// split into disjoint ranges across all seeders.
+    pub fn download(
+        &mut self,
+        root_hash: H256,
+        mut seeders: Vec<(PeerId, Option<Multiaddr>)>,
+    ) -> anyhow::Result<()> {
+        self.rank_seeders(&mut seeders);
+        let (first_peer_id, first_multiaddr) = seeders
+            .first()
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("No seeders to download from"))?;
+
+        self.pending_downloads.insert(root_hash, seeders);
+        self.request_file(first_peer_id, first_multiaddr, root_hash, None);
+        Ok(())
+    }
+
+    /// Downloads file with `root_hash` from seeders
+    /// providing it on the DHT
+    pub async fn download_from_providers(&mut self, root_hash: H256) -> anyhow::Result<()> {
+        let providers = get_providers(&self.network_command_sender, root_hash).await?;
+        if providers.is_empty() {
+            return Err(anyhow::anyhow!("No providers found for {:?}", root_hash));
+        }
+        debug!(
+            "(requester) found {} providers for {:?}",
+            providers.len(),
+            root_hash
+        );
+
+        // addresses of providers are known to the network
+        let seeders = providers
+            .into_iter()
+            .map(|peer_id| (peer_id, None))
+            .collect();
+        self.download(root_hash, seeders)
+    }
+
+    /// Requests `range` of chunks of file with `root_hash`
+    /// from seeder. Quote is negotiated as seeder responds.
+    pub fn request_file(
+        &mut self,
+        seeder_peer_id: PeerId,
+        seeder_multiaddr: Option<Multiaddr>,
+        root_hash: H256,
+        range: Option<Range<usize>>,
+    ) {
+        self.queue_file_request(seeder_peer_id, seeder_multiaddr, root_hash, range);
+        self.send_queued_request(seeder_peer_id, root_hash);
+    }
+
+    /// Tracks request of `range` of file from seeder
+    /// without sending it, e.g. till the range is freed
+    /// up by another seeder
+    fn queue_file_request(
+        &mut self,
+        seeder_peer_id: PeerId,
+        seeder_multiaddr: Option<Multiaddr>,
+        root_hash: H256,
+        range: Option<Range<usize>>,
+    ) {
+        self.pending_requests.insert(
+            (seeder_peer_id, root_hash),
+            PendingRequest {
+                seeder_multiaddr,
+                range,
+                sent_at: Instant::now(),
+                counters: 0,
+                accepted: None,
+            },
+        );
+    }
+
+    /// Sends file request queued for seeder
+    fn send_queued_request(&mut self, seeder_peer_id: PeerId, root_hash: H256) {
+        let request = match self.pending_requests.get_mut(&(seeder_peer_id, root_hash)) {
+            Some(request) => request,
+            None => return,
+        };
+        request.sent_at = Instant::now();
+        let (seeder_multiaddr, range) = (request.seeder_multiaddr.clone(), request.range.clone());
+        self.send_request(
+            seeder_peer_id,
+            seeder_multiaddr,
+            FileExchangeRequest::IWant {
+                attestation: self.attestation.clone(),
+                root_hash,
+                range,
+            },
+            SentRequest::Quote { root_hash },
+        );
+    }
+
+    /// Sends `request` to seeder, dialing it on `seeder_multiaddr`
+    /// first if provided. Response is handled once it arrives.
+    fn send_request(
+        &self,
+        seeder_peer_id: PeerId,
+        seeder_multiaddr: Option<Multiaddr>,
+        request: FileExchangeRequest,
+        sent: SentRequest,
+    ) {
+        let network_command_sender = self.network_command_sender.clone();
+        let response_sender = self.response_sender.clone();
+        tokio::spawn(async move {
+            let response = async {
+                if let Some(address) = seeder_multiaddr {
+                    dial(&network_command_sender, seeder_peer_id, Some(address)).await?;
+                }
+                send_file_request(&network_command_sender, seeder_peer_id, request).await
+            }
+            .await;
+            let _ = response_sender.send(SeederResponse {
+                seeder_peer_id,
+                request: sent,
+                response,
+            });
+        });
+    }
+
+    /// Handles seeder's response to a request
+    fn process_seeder_response(&mut self, response: SeederResponse) {
+        let SeederResponse {
+            seeder_peer_id,
+            request,
+            response,
+        } = response;
+        match request {
+            SentRequest::Quote { root_hash } => {
+                if let Err(e) = response.and_then(|r| self.negotiate(seeder_peer_id, root_hash, r))
+                {
+                    self.drop_file_request(seeder_peer_id, root_hash, e);
+                }
+            }
+            SentRequest::Accept { root_hash } => {
+                // seeder sends `IWillSeed` next
+                if let Err(e) = response.and_then(|r| r.into_ack()) {
+                    self.drop_file_request(seeder_peer_id, root_hash, e);
+                }
+            }
+            SentRequest::UpdateRange {
+                process_id,
+                root_hash,
+                end_sequence_no,
+                then,
+            } => {
+                self.shrinking.remove(&(seeder_peer_id, process_id));
+                let res = response.and_then(|r| r.into_ack()).and_then(|_| {
+                    let mut process = self.find_process(seeder_peer_id, process_id)?;
+                    process.end_sequence_no = process.end_sequence_no.min(end_sequence_no);
+                    self.storage.update_active_rprocess(process)
+                });
+                match res {
+                    Ok(()) => {
+                        for peer_id in then {
+                            self.send_queued_request(peer_id, root_hash);
+                        }
+                    }
+                    Err(e) => {
+                        error!(
+                            "(requester) failed to update range of process {} with error {}",
+                            process_id, e
+                        );
+                        for peer_id in then {
+                            self.pending_requests.remove(&(peer_id, root_hash));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Responds to seeder's quote by accepting
+    /// it or making a counter offer
+    fn negotiate(
+        &mut self,
+        seeder_peer_id: PeerId,
+        root_hash: H256,
+        response: FileExchangeResponse,
+    ) -> anyhow::Result<()> {
+        let (quote_id, range, size, terms) = match response {
+            FileExchangeResponse::Quote {
+                quote_id,
+                root_hash: quoted,
+                range,
+                size,
+                terms,
+            } if quoted == root_hash => (quote_id, range, size, terms),
+            other => return Err(other.into_error()),
+        };
+
+        let request =
+            self.process_quote(seeder_peer_id, quote_id, root_hash, range, size, terms)?;
+        let sent = match request {
+            FileExchangeRequest::Accept { .. } => SentRequest::Accept { root_hash },
+            _ => SentRequest::Quote { root_hash },
+        };
+        self.send_request(seeder_peer_id, None, request, sent);
+        Ok(())
+    }
+
+    /// Drops file request seeder failed. Seeders that fail
+    /// to take a range are left out, and the range is picked
+    /// up by others when rebalancing. Download waiting on
+    /// the first seeder is requested from the next one.
+    fn drop_file_request(&mut self, seeder_peer_id: PeerId, root_hash: H256, e: anyhow::Error) {
+        error!(
+            "(requester) file request of {:?} to {:?} failed with error {}",
+            root_hash, seeder_peer_id, e
+        );
+        self.pending_requests.remove(&(seeder_peer_id, root_hash));
+
+        let seeders = match self.pending_downloads.get_mut(&root_hash) {
+            Some(seeders) => seeders,
+            None => return,
+        };
+        seeders.retain(|(peer_id, _)| *peer_id != seeder_peer_id);
+        match seeders.first().cloned() {
+            Some((peer_id, multiaddr)) => self.request_file(peer_id, multiaddr, root_hash, None),
+            None => {
+                self.pending_downloads.remove(&root_hash);
+                error!("(requester) no seeders left to download {:?}", root_hash);
+            }
+        }
+    }
+
+    /// Asks seeder of `process` to shrink its range to end at
+    /// `end_sequence_no`. File is requested from `then` seeders,
+    /// whose requests are queued, once seeder agrees.
+    fn update_range(&mut self, process: &RProcess, end_sequence_no: usize, then: Vec<PeerId>) {
+        self.shrinking.insert((process.seeder_peer_id, process.id));
+        self.send_request(
+            process.seeder_peer_id,
+            None,
+            FileExchangeRequest::UpdateRange {
+                process_id: process.id,
+                end_sequence_no,
+            },
+            SentRequest::UpdateRange {
+                process_id: process.id,
+                root_hash: process.root_hash,
+                end_sequence_no,
+                then,
+            },
+        );
+    }
+
+    /// Recovers all active processes from storage,
+    /// re-establishes connection with their seeders,
+    /// and asks them to resume.
+    async fn recover_processes(&mut self) {
+        let processes = self.storage.get_all_active_rprocess().unwrap_or_default();
+        for (_, p) in processes {
+            // disputed processes aren't resumed, their
+            // chunks are fetched from other seeders
+            if p.state == ProcessState::Disputed {
+                continue;
+            }
+            if let Err(e) = self.resume_process(&p).await {
+                error!(
+                    "(requester) failed to resume process {} with seeder {:?} with error {}",
+                    p.id, p.seeder_peer_id, e
+                );
+            }
+        }
+    }
+
+    /// Asks seeder to resume `process`. Process is aborted if
+    /// seeder refuses, e.g. since it has ended the process.
+    async fn resume_process(&mut self, process: &RProcess) -> anyhow::Result<()> {
+        dial(
+            &self.network_command_sender,
+            process.seeder_peer_id,
+            process.seeder_multiaddr.clone(),
+        )
+        .await?;
+        self.expect_chunks_of(process).await?;
+
+        let response = send_file_request(
+            &self.network_command_sender,
+            process.seeder_peer_id,
+            FileExchangeRequest::Resume {
+                process_id: process.id,
+                sequence_no: process.sequence_no,
+                receipt: process.last_receipt.clone(),
+            },
+        )
+        .await?;
+        if let FileExchangeResponse::Rejected { reason } = &response {
+            self.abort_process(process.clone(), format!("Resume rejected: {}", reason))?;
+        }
+        response.into_ack()
+    }
+
+    /// Makes the network accept chunks of `process`
+    async fn expect_chunks_of(&mut self, process: &RProcess) -> anyhow::Result<()> {
+        expect_chunks(
+            &self.network_command_sender,
+            process.seeder_peer_id,
+            process.id,
+            Some(process.chunk_size),
+        )
+        .await?;
+        self.expected_chunks
+            .insert((process.seeder_peer_id, process.id));
+        Ok(())
+    }
+
+    /// Stops accepting chunks of processes that have ended
+    async fn forget_ended_processes(&mut self) {
+        let active = match self.storage.get_all_active_rprocess() {
+            Ok(active) => active,
+            Err(e) => {
+                error!("(requester) failed to read processes with error {}", e);
+                return;
+            }
+        };
+        let ended: Vec<(PeerId, u32)> = self
+            .expected_chunks
+            .iter()
+            .filter(|key| !active.contains_key(key))
+            .cloned()
+            .collect();
+        for (seeder_peer_id, process_id) in ended {
+            self.expected_chunks.remove(&(seeder_peer_id, process_id));
+            if let Err(e) = expect_chunks(
+                &self.network_command_sender,
+                seeder_peer_id,
+                process_id,
+                None,
+            )
+            .await
+            {
+                error!(
+                    "(requester) failed to stop accepting chunks of process {} with error {}",
+                    process_id, e
+                );
+            }
+        }
+    }
+
+    /// Ends `process` as aborted. Chunks it hasn't
+    /// received are assigned to other seeders on
+    /// rebalance.
+    fn abort_process(&self, mut process: RProcess, reason: String) -> anyhow::Result<()> {
+        process.state.transition(ProcessState::Aborted { reason })?;
+        debug!("(requester) process {} is {}", process.id, process.state);
+        self.storage
+            .remove_active_rprocess(&process.seeder_peer_id, process.id)
+    }
+
+    /// Asks `seeder_peer_id` to resume all processes with it
+    /// once connected again. Seeder keeps processes it recovered
+    /// on restart suspended until we do so.
+    async fn resume_processes_with(&mut self, seeder_peer_id: PeerId) {
+        let processes = match self.storage.get_all_active_rprocess() {
+            Ok(processes) => processes,
+            Err(e) => {
+                error!("(requester) failed to read processes with error {}", e);
+                return;
+            }
+        };
+        for p in processes.into_values() {
+            if p.seeder_peer_id != seeder_peer_id || p.state == ProcessState::Disputed {
+                continue;
+            }
+            if let Err(e) = self.expect_chunks_of(&p).await {
+                error!(
+                    "(requester) failed to accept chunks of process {} with error {}",
+                    p.id, e
+                );
+                continue;
+            }
+            if let Err(e) = send_file_request_detached(
+                &self.network_command_sender,
+                seeder_peer_id,
+                FileExchangeRequest::Resume {
+                    process_id: p.id,
+                    sequence_no: p.sequence_no,
+                    receipt: p.last_receipt.clone(),
+                },
+            )
+            .await
+            {
+                error!(
+                    "(requester) failed to resume process {} with seeder {:?} with error {}",
+                    p.id, seeder_peer_id, e
+                );
+            }
+        }
+    }
+
+    /// Dials seeders of active processes that we got
+    /// disconnected from, e.g. since they restarted.
+    /// Processes are resumed once connected.
+    fn reconnect_seeders(&self) {
+        let connected: HashSet<PeerId> = self.peer_table.connected().into_iter().collect();
+        let processes = self.storage.get_all_active_rprocess().unwrap_or_default();
+        let seeders: HashMap<PeerId, Option<Multiaddr>> = processes
+            .into_values()
+            .filter(|p| p.state != ProcessState::Disputed)
+            .filter(|p| !connected.contains(&p.seeder_peer_id))
+            .map(|p| (p.seeder_peer_id, p.seeder_multiaddr))
+            .collect();
+        for (seeder_peer_id, multiaddr) in seeders {
+            let network_command_sender = self.network_command_sender.clone();
+            tokio::spawn(async move {
+                if let Err(e) = dial(&network_command_sender, seeder_peer_id, multiaddr).await {
+                    debug!(
+                        "(requester) failed to reconnect to seeder {:?} with error {}",
+                        seeder_peer_id, e
+                    );
+                }
+            });
+        }
+    }
+
+    fn find_process(&self, seeder_peer_id: PeerId, process_id: u32) -> anyhow::Result<RProcess> {
+        self.storage
+            .find_active_rprocess(&seeder_peer_id, process_id)?
+            .ok_or_else(|| anyhow::anyhow!("Process {} does not exists", process_id))
+    }
+
+    /// Evaluates seeder's quote with pricing policy. Returns
+    /// request accepting it or making a counter offer.
+    fn process_quote(
+        &mut self,
+        seeder_peer_id: PeerId,
+        quote_id: u32,
+        root_hash: H256,
+        range: Range<usize>,
+        size: usize,
+        terms: QuoteTerms,
+    ) -> anyhow::Result<FileExchangeRequest> {
+        let request = self
+            .pending_requests
+            .get_mut(&(seeder_peer_id, root_hash))
+            .ok_or_else(|| anyhow::anyhow!("File {:?} wasn't requested", root_hash))?;
+
+        if terms.chunk_size == 0 || terms.chunk_size > MAX_CHUNK_SIZE {
+            return Err(anyhow::anyhow!(
+                "Chunk size should be non zero and at most {}",
+                MAX_CHUNK_SIZE
+            ));
+        }
+
+        if let Some(requested) = &request.range {
+            if requested != &range {
+                return Err(anyhow::anyhow!(
+                    "Requested range {:?}, seeder quoted {:?}",
+                    requested,
+                    range
+                ));
+            }
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        if terms.valid_until < now {
+            return Err(anyhow::anyhow!("Quote {} has expired", quote_id));
+        }
+
+        let request_to_send =
+            match self
+                .pricing_policy
+                .evaluate(&terms, range.len(), request.counters)
+            {
+                QuoteDecision::Accept => {
+                    request.accepted = Some(AcceptedQuote {
+                        quote_id,
+                        range,
+                        size,
+                        terms,
+                    });
+                    FileExchangeRequest::Accept { quote_id }
+                }
+                QuoteDecision::Counter(chunk_price) => {
+                    request.counters += 1;
+                    FileExchangeRequest::CounterOffer {
+                        quote_id,
+                        chunk_price,
+                    }
+                }
+                QuoteDecision::Reject => {
+                    self.pending_requests.remove(&(seeder_peer_id, root_hash));
+                    return Err(anyhow::anyhow!("Quote {} rejected", quote_id));
+                }
+            };
+        request.sent_at = Instant::now();
+        Ok(request_to_send)
+    }
+
+    fn process_seed_acceptance(
+        &mut self,
+        seeder_peer_id: PeerId,
+        process_id: u32,
+        attestation: Attestation,
+        root_hash: H256,
+    ) -> anyhow::Result<()> {
+        let seeder_address = attestation.verify(&seeder_peer_id)?;
+        self.storage.store_peer_attestation(&attestation)?;
+
+        let request = self
+            .pending_requests
+            .remove(&(seeder_peer_id, root_hash))
+            .ok_or_else(|| anyhow::anyhow!("File {:?} wasn't requested", root_hash))?;
+
+        let AcceptedQuote {
+            range, size, terms, ..
+        } = request
+            .accepted
+            .filter(|q| q.quote_id == process_id)
+            .ok_or_else(|| anyhow::anyhow!("Quote {} wasn't accepted", process_id))?;
+        let chunk_size = terms.chunk_size;
+        let chunk_price = terms.chunk_price;
+        let payment_schedule = terms.payment_schedule;
+
+        let download = match self.storage.find_active_download(&root_hash)? {
+            Some(download) => {
+                // all seeders should agree on the file
+                if download.size != size || download.chunk_size != chunk_size {
+                    return Err(anyhow::anyhow!(
+                        "Seeder {:?} disagrees on file {:?}",
+                        seeder_peer_id,
+                        root_hash
+                    ));
+                }
+                download
+            }
+            None => {
+                // create (or truncate) file to write chunks to
+                OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .truncate(true)
+                    .open(self.file_path(&root_hash))?;
+
+                let download = Download {
+                    root_hash,
+                    chunk_size,
+                    size,
+                    seeders: self
+                        .pending_downloads
+                        .get(&root_hash)
+                        .cloned()
+                        .unwrap_or_else(|| {
+                            vec![(seeder_peer_id, request.seeder_multiaddr.clone())]
+                        }),
+                };
+                self.storage.update_active_download(download.clone())?;
+                download
+            }
+        };
+
+        if range.end > download.chunk_count() {
+            return Err(anyhow::anyhow!("Invalid chunk range {:?}", range));
+        }
+
+        self.seeder_stats
+            .entry(seeder_peer_id)
+            .or_default()
+            .chunk_price = chunk_price;
+
+        let process = RProcess {
+            id: process_id,
+            root_hash,
+            sender_address: seeder_address,
+            seeder_peer_id,
+            seeder_multiaddr: request.seeder_multiaddr,
+            chunk_size,
+            chunk_price,
+            payment_schedule,
+            size,
+            sequence_no: range.start,
+            end_sequence_no: range.end,
+            rfp_sequence_no: range.start,
+            last_receipt: None,
+            state: ProcessState::Negotiating,
+        };
+        self.storage.update_active_rprocess(process)?;
+        Ok(())
+    }
+
+    /// Spreads download of file of process across its
+    /// seeders, if download was waiting for the first
+    /// seeder to accept
+    fn spread_pending_download(
+        &mut self,
+        seeder_peer_id: PeerId,
+        process_id: u32,
+    ) -> anyhow::Result<()> {
+        let process = self.find_process(seeder_peer_id, process_id)?;
+        let seeders = match self.pending_downloads.remove(&process.root_hash) {
+            Some(seeders) => seeders,
+            None => return Ok(()),
+        };
+        let download = self
+            .storage
+            .find_active_download(&process.root_hash)?
+            .ok_or_else(|| anyhow::anyhow!("Download {:?} does not exists", process.root_hash))?;
+        self.spread_download(&download, process, seeders);
+        Ok(())
+    }
+
+    /// Splits chunks of `process`, which spans the whole file,
+    /// into equal disjoint ranges across `seeders`. Other
+    /// seeders are requested their ranges once seeder of
+    /// `process` agrees to give them up.
+    fn spread_download(
+        &mut self,
+        download: &Download,
+        process: RProcess,
+        seeders: Vec<(PeerId, Option<Multiaddr>)>,
+    ) {
+        let others: Vec<_> = seeders
+            .into_iter()
+            .filter(|(peer_id, _)| *peer_id != process.seeder_peer_id)
+            .collect();
+        let chunk_count = download.chunk_count();
+        let share = chunk_count / (others.len() + 1);
+        if others.is_empty() || share <= RANGE_SHRINK_MARGIN {
+            // not worth splitting. Rest of the seeders
+            // might still be used when rebalancing.
+            return;
+        }
+
+        for (i, (peer_id, multiaddr)) in others.iter().enumerate() {
+            let start = share * (i + 1);
+            let end = if i + 1 == others.len() {
+                chunk_count
+            } else {
+                start + share
+            };
+            self.queue_file_request(
+                *peer_id,
+                multiaddr.clone(),
+                download.root_hash,
+                Some(start..end),
+            );
+        }
+        let then = others.into_iter().map(|(peer_id, _)| peer_id).collect();
+        self.update_range(&process, share, then);
+    }
+
+    /// Rebalances ranges of active downloads. Idle seeders (ones
+    /// without an active process for the download) are assigned
+    /// chunks that no process covers, or else take over the tail
+    /// of the range of the process expected to finish last.
+    /// Faster and cheaper seeders are assigned first.
+    async fn rebalance(&mut self) {
+        // drop stale pending requests
+        self.pending_requests
+            .retain(|_, r| r.sent_at.elapsed() < PENDING_REQUEST_TIMEOUT);
+
+        self.reconnect_seeders();
+        self.forget_ended_processes().await;
+        let downloads = self.storage.get_all_active_downloads().unwrap_or_default();
+        for (root_hash, download) in downloads {
+            if let Err(e) = self.rebalance_download(download) {
+                error!(
+                    "(requester) failed to rebalance download {:?} with error {}",
+                    root_hash, e
+                );
+            }
+        }
+    }
+
+    fn rebalance_download(&mut self, download: Download) -> anyhow::Result<()> {
+        let processes: Vec<RProcess> = self
+            .storage
+            .get_all_active_rprocess()?
+            .into_values()
+            .filter(|p| p.root_hash == download.root_hash)
+            .collect();
+
+        let busy: HashSet<PeerId> = processes
+            .iter()
+            .map(|p| p.seeder_peer_id)
+            .chain(
+                self.pending_requests
+                    .keys()
+                    .filter(|(_, root_hash)| *root_hash == download.root_hash)
+                    .map(|(peer_id, _)| *peer_id),
+            )
+            .collect();
+        let mut idle: Vec<(PeerId, Option<Multiaddr>)> = download
+            .seeders
+            .iter()
+            .filter(|(peer_id, _)| !busy.contains(peer_id))
+            .cloned()
+            .collect();
+        self.rank_seeders(&mut idle);
+        if idle.is_empty() {
+            return Ok(());
+        }
+
+        let open = self.open_download(&download.root_hash)?;
+        let received: Vec<bool> = (0..download.chunk_count())
+            .map(|i| open.is_received(i))
+            .collect();
+        for (peer_id, multiaddr) in idle {
+            // chunks that are neither received nor covered by
+            // any process or pending request
+            let mut covered = received.clone();
+            for range in processes
+                .iter()
+                .filter(|p| p.state != ProcessState::Disputed)
+                .map(|p| p.sequence_no..p.end_sequence_no)
+                .chain(
+                    self.pending_requests
+                        .iter()
+                        .filter(|((_, root_hash), _)| *root_hash == download.root_hash)
+                        .filter_map(|(_, r)| r.range.clone()),
+                )
+            {
+                for c in covered.iter_mut().take(range.end).skip(range.start) {
+                    *c = true;
+                }
+            }
+            if let Some(start) = covered.iter().position(|c| !*c) {
+                let end = covered[start..]
+                    .iter()
+                    .position(|c| *c)
+                    .map_or(covered.len(), |len| start + len);
+                self.request_file(peer_id, multiaddr, download.root_hash, Some(start..end));
+                continue;
+            }
+
+            // take over tail of the process expected to finish last
+            let eta = |p: &RProcess| {
+                let rate = self
+                    .seeder_stats
+                    .get(&p.seeder_peer_id)
+                    .and_then(|s| s.rate())
+                    .unwrap_or(f64::MIN_POSITIVE);
+                (p.remaining() * p.chunk_size) as f64 / rate
+            };
+            let victim = processes
+                .iter()
+                .filter(|p| p.state != ProcessState::Disputed)
+                .filter(|p| !self.shrinking.contains(&(p.seeder_peer_id, p.id)))
+                .filter(|p| p.unpaid_remaining() > 2 * RANGE_SHRINK_MARGIN)
+                .max_by(|a, b| eta(a).total_cmp(&eta(b)));
+            let victim = match victim {
+                Some(victim) => victim.clone(),
+                None => break,
+            };
+
+            // chunks paid for in advance stay with the victim
+            let mid = victim.sequence_no.max(victim.rfp_sequence_no)
+                + RANGE_SHRINK_MARGIN
+                + (victim.unpaid_remaining() - RANGE_SHRINK_MARGIN) / 2;
+            let end = victim.end_sequence_no;
+            debug!(
+                "(requester) moving chunks {:?} from {:?} to {:?}",
+                mid..end,
+                victim.seeder_peer_id,
+                peer_id
+            );
+            self.queue_file_request(peer_id, multiaddr, download.root_hash, Some(mid..end));
+            self.update_range(&victim, mid, vec![peer_id]);
+        }
+        Ok(())
+    }
+
+    /// Writes chunk received out of order within the window
+    /// of the process. Returns index of the first chunk that
+    /// hasn't been received, i.e. the cumulative ack.
+    fn process_data_chunk(
+        &mut self,
+        seeder_peer_id: PeerId,
+        process_id: u32,
+        sequence_no: usize,
+        chunk: Vec<u8>,
+        proof: &[[u8; 32]],
+    ) -> anyhow::Result<usize> {
+        let mut process = self.find_process(seeder_peer_id, process_id)?;
+        // process is stored only if it changes
+        let (state, sequence_no_before) = (process.state.clone(), process.sequence_no);
+
+        match process.state {
+            ProcessState::Negotiating => process.state.transition(ProcessState::Transferring)?,
+            // chunks sent before RFP might still be in flight
+            ProcessState::Transferring | ProcessState::AwaitingPayment => {}
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Process {} is {}",
+                    process_id,
+                    process.state
+                ))
+            }
+        }
+
+        if sequence_no >= process.end_sequence_no || sequence_no >= process.sequence_no + MAX_WINDOW
+        {
+            return Err(anyhow::anyhow!(
+                "Chunk {} is out of window of process {}",
+                sequence_no,
+                process_id
+            ));
+        }
+
+        let root_hash = process.root_hash;
+        let chunk_count = process.size.div_ceil(process.chunk_size);
+
+        // Ignore chunks we already have, but ack them again.
+        // Seeder resends them after it goes back on loss.
+        if sequence_no < process.sequence_no
+            || self.open_download(&root_hash)?.is_received(sequence_no)
+        {
+            return Ok(process.sequence_no);
+        }
+
+        let offset = sequence_no * process.chunk_size;
+        let expected_len = process.chunk_size.min(process.size.saturating_sub(offset));
+        if chunk.len() != expected_len
+            || !verify_chunk(&root_hash, chunk_count, sequence_no, &chunk, proof)
+        {
+            let reason = format!("Chunk {} failed verification", sequence_no);
+            let download = self
+                .storage
+                .find_active_download(&root_hash)?
+                .ok_or_else(|| anyhow::anyhow!("Download {:?} does not exists", root_hash))?;
+            self.drop_offender(process, download, reason.clone())?;
+            return Err(anyhow::anyhow!(reason));
+        }
+
+        let open = self.open_download(&root_hash)?;
+        open.file.write_all_at(&chunk, offset as u64)?;
+        let (index, word) = open.set_received(sequence_no);
+        self.storage
+            .store_received_chunks(&root_hash, index, word)?;
+
+        let stats = self.seeder_stats.entry(seeder_peer_id).or_default();
+        let now = Instant::now();
+        if let Some(last_chunk_at) = stats.last_chunk_at {
+            stats.elapsed += now - last_chunk_at;
+            stats.bytes += chunk.len();
+        }
+        stats.last_chunk_at = Some(now);
+
+        let open = &self.open_downloads[&root_hash];
+        while process.sequence_no < process.end_sequence_no && open.is_received(process.sequence_no)
+        {
+            process.sequence_no += 1;
+        }
+        let ack = process.sequence_no;
+        let download_complete = open.remaining == 0;
+
+        if process.is_complete() {
+            process.state.transition(ProcessState::Completed)?;
+            debug!("(requester) process {} completed", process_id);
+            self.reputations
+                .record(seeder_peer_id, ReputationEvent::CompletedTransfer);
+            self.storage
+                .remove_active_rprocess(&seeder_peer_id, process_id)?;
+        } else if process.state != state || process.sequence_no != sequence_no_before {
+            self.storage.update_active_rprocess(process)?;
+        }
+
+        if download_complete {
+            self.complete_download(&root_hash)?;
+        }
+        Ok(ack)
+    }
+
+    /// Verifies the stitched file of download with `root_hash`,
+    /// all of whose chunks have been received, against the root
+    /// hash. Download is kept and its chunks are fetched again
+    /// if file doesn't match.
+    fn complete_download(&mut self, root_hash: &H256) -> anyhow::Result<()> {
+        if let Some(open) = self.open_downloads.remove(root_hash) {
+            open.file.sync_data()?;
+        }
+        let download = self
+            .storage
+            .find_active_download(root_hash)?
+            .ok_or_else(|| anyhow::anyhow!("Download {:?} does not exists", root_hash))?;
+        let received = File::open(
+            &self.file_path(root_hash),
+            download.chunk_size,
+            U256::zero(),
+        )?;
+        if received.metadata.root_hash == *root_hash {
+            debug!("(requester) received file {:?}", root_hash);
+            return self.storage.remove_active_download(root_hash);
+        }
+
+        // chunks were verified as they arrived, thus
+        // file has changed on disk since then
+        error!(
+            "(requester) received file does not match root hash {:?}, fetching it again",
+            root_hash
+        );
+        self.storage.remove_received_chunks(root_hash)
+    }
+
+    /// Records seeder of `process` for sending an invalid chunk
+    /// and aborts the process. Seeder is dropped from `download`,
+    /// so that the rest of its range is fetched from others.
+    fn drop_offender(
+        &self,
+        process: RProcess,
+        mut download: Download,
+        reason: String,
+    ) -> anyhow::Result<()> {
+        let seeder_peer_id = process.seeder_peer_id;
+        self.reputations
+            .record(seeder_peer_id, ReputationEvent::InvalidChunk);
+        download
+            .seeders
+            .retain(|(peer_id, _)| *peer_id != seeder_peer_id);
+        self.storage.update_active_download(download)?;
+        self.abort_process(process, reason)
+    }
+
+    /// Validates and signs receipt of RFP for
+    /// chunks till `rfp_sequence_no` (exclusive)
+    fn process_rfp(
+        &mut self,
+        seeder_peer_id: PeerId,
+        process_id: u32,
+        rfp_sequence_no: usize,
+        receipt: ReceiptWithSignatures,
+    ) -> anyhow::Result<ReceiptWithSignatures> {
+        let mut process = self.find_process(seeder_peer_id, process_id)?;
+
+        // Seeder sends RFP again if our confirmation
+        // never reached it
+        if let Some(last_receipt) = &process.last_receipt {
+            if rfp_sequence_no == process.rfp_sequence_no
+                && last_receipt.receipt() == receipt.receipt()
+            {
+                return Ok(last_receipt.clone());
+            }
+        }
+
+        match process.state {
+            ProcessState::Negotiating | ProcessState::Transferring => {
+                process.state.transition(ProcessState::AwaitingPayment)?
+            }
+            // RFP is sent again if we restarted before paying
+            ProcessState::AwaitingPayment => {}
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Process {} is {}",
+                    process_id,
+                    process.state
+                ))
+            }
+        }
+
+        // Only pay for chunks that haven't been paid
+        // for, as per the agreed schedule
+        if !process.payment_schedule.is_valid_rfp(
+            process.sequence_no,
+            process.rfp_sequence_no,
+            process.end_sequence_no,
+            rfp_sequence_no,
+        ) {
+            process.state.transition(ProcessState::Disputed)?;
+            self.storage.update_active_rprocess(process)?;
+            self.reputations
+                .record(seeder_peer_id, ReputationEvent::InvalidReceipt);
+            return Err(anyhow::anyhow!(
+                "Invalid RFP till chunk {} in process {}",
+                rfp_sequence_no,
+                process_id
+            ));
+        }
+
+        // only pay addresses seeder has attested to
+        self.storage
+            .ensure_peer_address(&seeder_peer_id, &process.sender_address)?;
+        self.storage.update_active_rprocess(process.clone())?;
+
+        let unpaid = rfp_sequence_no - process.rfp_sequence_no;
+        let amount = process.chunk_price * unpaid;
+        let receipt =
+            match self
+                .wallet
+                .process_incoming_rfp(process.sender_address, amount, receipt)
+            {
+                Ok(receipt) => receipt,
+                // failing to pay isn't seeder's fault. Rest
+                // of the chunks are left to other seeders.
+                Err(e) if !self.wallet.can_pay(amount) => {
+                    self.abort_process(process, format!("Can't pay: {}", e))?;
+                    return Err(e);
+                }
+                Err(e) => {
+                    process.state.transition(ProcessState::Disputed)?;
+                    self.storage.update_active_rprocess(process)?;
+                    self.reputations
+                        .record(seeder_peer_id, ReputationEvent::InvalidReceipt);
+                    return Err(e);
+                }
+            };
+
+        process.rfp_sequence_no = rfp_sequence_no;
+        process.last_receipt = Some(receipt.clone());
+        if process.is_complete() {
+            process.state.transition(ProcessState::Completed)?;
+            debug!("(requester) process {} completed", process_id);
+            self.reputations
+                .record(seeder_peer_id, ReputationEvent::CompletedTransfer);
+            self.storage
+                .remove_active_rprocess(&seeder_peer_id, process_id)?;
+        } else {
+            process.state.transition(ProcessState::Transferring)?;
+            self.storage.update_active_rprocess(process)?;
+        }
+        Ok(receipt)
+    }
+
+    /// Acks chunks before `sequence_no` cumulatively
+    async fn send_ack(&self, seeder_peer_id: PeerId, process_id: u32, sequence_no: usize) {
+        if let Err(e) = send_file_request_detached(
+            &self.network_command_sender,
+            seeder_peer_id,
+            FileExchangeRequest::ChunkAck {
+                process_id,
+                sequence_no,
+            },
+        )
+        .await
+        {
+            error!(
+                "(requester) failed to ack chunks of process {} with error {}",
+                process_id, e
+            );
+        }
+    }
+
+    /// Sends acks left pending
+    async fn flush_acks(&mut self) {
+        for ((seeder_peer_id, process_id), (ack, _)) in std::mem::take(&mut self.pending_acks) {
+            self.send_ack(seeder_peer_id, process_id, ack).await;
+        }
+    }
+
+    pub async fn run(&mut self) {
+        self.recover_processes().await;
+
+        let mut interval = time::interval(time::Duration::from_secs(5));
+        let mut ack_interval = time::interval(ACK_DELAY);
+        loop {
+            select! {
+                _ = interval.tick() => {
+                    self.rebalance().await;
+                },
+                _ = ack_interval.tick() => {
+                    self.flush_acks().await;
+                },
+                Some(response) = self.response_receiver.recv() => {
+                    self.process_seeder_response(response);
+                },
+                event = self.network_event_receiver.recv() => match event {
+                    Ok(event) => self.handle_network_event(event).await,
+                    // lost chunks are sent again, and seeders
+                    // retry requests that weren't responded to
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("(requester) missed {} network events", n);
+                    }
+                    Err(RecvError::Closed) => return,
+                }
+            }
+        }
+    }
+
+    async fn handle_network_event(&mut self, event: NetworkEvent) {
+        match event {
+            // left to seeder running on the same network
+            NetworkEvent::FileExchangeRequest { request, .. } if !request.is_for_requester() => {}
+            NetworkEvent::FileExchangeRequest {
+                sender_peer_id,
+                request_id,
+                request,
+            } => {
+                let seed_process_id = match &request {
+                    FileExchangeRequest::IWillSeed { process_id, .. } => Some(*process_id),
+                    _ => None,
+                };
+                let response = match request {
+                    FileExchangeRequest::IWillSeed {
+                        process_id,
+                        attestation,
+                        root_hash,
+                    } => {
+                        let res = self.process_seed_acceptance(
+                            sender_peer_id,
+                            process_id,
+                            attestation,
+                            root_hash,
+                        );
+                        // chunks have to be accepted before
+                        // seeder starts sending them
+                        let res = match res {
+                            Ok(_) => match self.find_process(sender_peer_id, process_id) {
+                                Ok(process) => self.expect_chunks_of(&process).await,
+                                Err(e) => Err(e),
+                            },
+                            Err(e) => Err(e),
+                        };
+                        match res {
+                            Ok(_) => FileExchangeResponse::Ack,
+                            Err(e) => {
+                                error!(
+                                    "(requester) failed to start process {} with error {}",
+                                    process_id, e
+                                );
+                                FileExchangeResponse::rejected(&e)
+                            }
+                        }
+                    }
+                    FileExchangeRequest::Rfp {
+                        process_id,
+                        rfp_sequence_no,
+                        receipt,
+                    } => {
+                        match self.process_rfp(sender_peer_id, process_id, rfp_sequence_no, receipt)
+                        {
+                            Ok(receipt) => FileExchangeResponse::Receipt { receipt },
+                            Err(e) => {
+                                error!(
+                                    "(requester) rejected rfp of process {} with error {}",
+                                    process_id, e
+                                );
+                                FileExchangeResponse::rejected(&e)
+                            }
+                        }
+                    }
+                    _ => unreachable!("only requests for requester are handled"),
+                };
+                let accepted =
+                    seed_process_id.filter(|_| matches!(response, FileExchangeResponse::Ack));
+
+                if let Err(e) = send_file_response(
+                    &self.network_command_sender,
+                    sender_peer_id,
+                    request_id,
+                    response,
+                )
+                .await
+                {
+                    error!(
+                        "(requester) failed to respond to {:?} with error {}",
+                        sender_peer_id, e
+                    );
+                }
+
+                // spread download only after responding, so that
+                // range update reaches seeder after the ack
+                if let Some(process_id) = accepted {
+                    if let Err(e) = self.spread_pending_download(sender_peer_id, process_id) {
+                        error!(
+                            "(requester) failed to spread download of process {} with error {}",
+                            process_id, e
+                        );
+                    }
+                }
+            }
+            NetworkEvent::ChunkReceived {
+                sender_peer_id,
+                chunk:
+                    Chunk {
+                        process_id,
+                        sequence_no,
+                        data,
+                        proof,
+                    },
+            } => {
+                match self.process_data_chunk(sender_peer_id, process_id, sequence_no, data, &proof)
+                {
+                    Ok(ack) => {
+                        let key = (sender_peer_id, process_id);
+                        let unacked = self.pending_acks.get(&key).map_or(0, |(_, n)| *n) + 1;
+                        // chunks received out of order, i.e. after
+                        // a loss, are acked right away so that
+                        // seeder goes back sooner
+                        if unacked >= ACK_EVERY || ack != sequence_no + 1 {
+                            self.pending_acks.remove(&key);
+                            self.send_ack(sender_peer_id, process_id, ack).await;
+                        } else {
+                            self.pending_acks.insert(key, (ack, unacked));
+                        }
+                    }
+                    Err(e) => {
+                        debug!(
+                            "(requester) dropped chunk {} of process {} with error {}",
+                            sequence_no, process_id, e
+                        );
+                    }
+                }
+            }
+            NetworkEvent::PeerConnected { peer_id } => {
+                self.resume_processes_with(peer_id).await;
+            }
+            _ => {}
+        }
+    }
 }
@@ -1,12 +1,71 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-use super::network::file_exchange::FileExchangeRequest;
-use super::network::NetworkEvent;
+use super::bandwidth::BandwidthShaper;
+use super::catalog::Catalog;
+use super::chunk_store::ChunkStore;
+use super::flow_control::{SlidingWindow, ACK_TIMEOUT};
+use super::network::chunk_stream::Chunk;
+use super::network::file_exchange::{
+    FileExchangeRequest, FileExchangeResponse, PaymentSchedule, QuoteTerms,
+};
+use super::network::{
+    close_chunk_stream, dial, send_chunk, send_file_response, start_providing, stop_providing,
+    Command, Network, NetworkEvent,
+};
+use super::pricing::{QuoteContext, QuoteDecision, SeederPricingPolicy};
+use super::process_state::ProcessState;
+use super::reputation::{ReputationEvent, Reputations};
 use super::storage::Storage;
-use super::wallet::Wallet;
-use ethers::types::{Address, U256};
+use super::wallet::{Attestation, ReceiptWithSignatures, Wallet};
+use ethers::types::{Address, H256, U256};
+use libp2p::PeerId;
+use log::{debug, error, warn};
 use serde::{Deserialize, Serialize};
-use tokio::{select, sync::broadcast, time};
+use std::{
+    ops::Range,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    select,
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc, oneshot,
+    },
+    time::{self, Duration, Instant},
+};
+
+/// Duration for which a quote is valid
+const QUOTE_VALIDITY: Duration = Duration::from_secs(60);
+
+/// Interval at which provider records on the
+/// DHT are synced with files in catalog
+const CATALOG_SYNC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Delay before a process that failed
+/// to make progress is driven again
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Max no. of chunks cached in memory
+const CHUNK_CACHE_CAPACITY: usize = 1024;
+
+/// Time after which a disputed process that
+/// requester hasn't resumed is aborted
+const DISPUTE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Cap on concurrent processes with a requester,
+/// so that a single requester can't exhaust seeder
+const MAX_PROCESSES_PER_REQUESTER: usize = 8;
+
+/// Max no. of counter offers accepted
+/// for a quote before it is rejected
+const MAX_COUNTER_OFFERS: usize = 4;
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
 
 #[derive(Serialize, Deserialize)]
 struct FileRFP {
@@ -14,111 +73,1348 @@ struct FileRFP {
     sequence_no: u32,
 }
 
+/// RFP sent to requester that
+/// hasn't been confirmed yet
+#[derive(Serialize, Deserialize, Clone)]
+struct PendingRfp {
+    receipt: ReceiptWithSignatures,
+    /// Index of chunk (exclusive) RFP pays till
+    rfp_sequence_no: usize,
+}
+
 /// Process object for tracking file transfer
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct SProcess {
     pub id: u32,
+    /// Root hash of file being sent
+    root_hash: H256,
     requester_address: Address,
-    /// Index of chunk of data being sent
+    requester_peer_id: PeerId,
+    /// Index of the next chunk to send
     sequence_no: usize,
-    /// `rfp_sequence_no` should only
-    /// be 1 less than `sequence_no`
+    /// Index of chunk (exclusive) at which
+    /// the process ends. Requester can shrink it
+    /// to fetch rest of the chunks from other seeders.
+    end_sequence_no: usize,
+    /// Index of the first chunk that
+    /// hasn't been paid for
     rfp_sequence_no: usize,
+    /// Price per chunk as per accepted quote
+    chunk_price: U256,
+    payment_schedule: PaymentSchedule,
+    /// Latest RFP that hasn't been confirmed yet.
+    /// It is sent again on resume instead of creating
+    /// a new one, so that requester isn't charged twice.
+    pending_rfp: Option<PendingRfp>,
+    state: ProcessState,
 }
 
-#[derive(Serialize, Deserialize)]
-struct File {
-    id: u32,
-    chunk_size: usize,
-    chunk_price: U256,
-    file: Vec<u8>,
+impl SProcess {
+    pub fn state(&self) -> &ProcessState {
+        &self.state
+    }
+}
+
+/// Quote sent to requester that it
+/// hasn't accepted yet
+struct PendingQuote {
+    requester_address: Address,
+    root_hash: H256,
+    range: Range<usize>,
+    /// Chunk price originally quoted, counter
+    /// offers are always priced against it
+    quoted_price: U256,
+    /// No. of counter offers received
+    counters: usize,
+    terms: QuoteTerms,
+}
+
+/// Request sent to requester
+enum SentRequest {
+    IWillSeed,
+    Rfp,
+}
+
+impl SentRequest {
+    fn new(request: &FileExchangeRequest) -> anyhow::Result<Self> {
+        match request {
+            FileExchangeRequest::IWillSeed { .. } => Ok(SentRequest::IWillSeed),
+            FileExchangeRequest::Rfp { .. } => Ok(SentRequest::Rfp),
+            _ => Err(anyhow::anyhow!("Seeder does not send {:?}", request)),
+        }
+    }
+}
+
+/// Requester's response to a request of process
+struct RequesterResponse {
+    requester_peer_id: PeerId,
+    process_id: u32,
+    request: SentRequest,
+    response: anyhow::Result<FileExchangeResponse>,
 }
 
-struct FileSeeder {
+pub struct FileSeeder {
     storage: Storage,
     wallet: Wallet,
+    reputations: Reputations,
+    pricing_policy: Box<dyn SeederPricingPolicy>,
+    /// Quotes sent keyed by requester's
+    /// peer id and quote id
+    quotes: HashMap<(PeerId, u32), PendingQuote>,
+    /// Active processes. Storage is only
+    /// updated when their state changes, i.e.
+    /// not for every chunk sent.
+    processes: HashMap<u32, SProcess>,
+    /// Processes are driven by waking them up with
+    /// their id. A woken up process makes as much
+    /// progress as it can and wakes itself up again
+    /// if it can make more.
+    wake_sender: mpsc::UnboundedSender<u32>,
+    wake_receiver: mpsc::UnboundedReceiver<u32>,
+    /// Responses to requests sent to requesters are
+    /// awaited outside of the event loop, so that seeder
+    /// never waits on a requester (which might be waiting
+    /// on seeder) to make progress.
+    response_sender: mpsc::UnboundedSender<RequesterResponse>,
+    response_receiver: mpsc::UnboundedReceiver<RequesterResponse>,
+    /// Flow control of chunks in flight per process
+    windows: HashMap<u32, SlidingWindow>,
+    /// Upload bandwidth limits across processes
+    bandwidth: BandwidthShaper,
     rfp_sent: HashSet<u32>,
-    main_file: File,
+    /// Processes recovered from storage on startup
+    /// that are waiting for requester to resume
+    suspended: HashSet<u32>,
+    /// Processes halted on a dispute, along with
+    /// when. Aborted unless resumed in time.
+    disputed: HashMap<u32, Instant>,
+    catalog: Catalog,
+    /// Files in catalog announced on the DHT
+    provided: HashSet<H256>,
+    chunk_store: ChunkStore,
+    /// Binds our peer id to our address
+    attestation: Attestation,
     network_event_receiver: broadcast::Receiver<NetworkEvent>,
+    network_command_sender: mpsc::Sender<Command>,
 }
 
 impl FileSeeder {
-    fn process_file_request(&self, requester_address: Address) {
+    /// Seeds files in `catalog` to requesters
+    /// connecting through `network`
+    pub fn new(
+        storage: Storage,
+        wallet: Wallet,
+        pricing_policy: Box<dyn SeederPricingPolicy>,
+        catalog: Catalog,
+        network: &Network,
+    ) -> Self {
+        let (wake_sender, wake_receiver) = mpsc::unbounded_channel();
+        let (response_sender, response_receiver) = mpsc::unbounded_channel();
+        let attestation = wallet.attest(network.keypair.public().to_peer_id());
+        Self {
+            reputations: Reputations::new(storage.clone(), network.ban_list()),
+            storage,
+            wallet,
+            pricing_policy,
+            quotes: HashMap::new(),
+            processes: HashMap::new(),
+            wake_sender,
+            wake_receiver,
+            response_sender,
+            response_receiver,
+            windows: HashMap::new(),
+            bandwidth: BandwidthShaper::default(),
+            rfp_sent: HashSet::new(),
+            suspended: HashSet::new(),
+            disputed: HashMap::new(),
+            chunk_store: ChunkStore::new(catalog.clone(), CHUNK_CACHE_CAPACITY),
+            catalog,
+            provided: HashSet::new(),
+            attestation,
+            network_event_receiver: network.network_event_receiver(),
+            network_command_sender: network.network_command_sender(),
+        }
+    }
+
+    /// Recovers all active processes from storage.
+    /// Recovered processes stay suspended until the
+    /// requester sends `Resume`, since only requester knows
+    /// which chunks it has received.
+    async fn recover_processes(&mut self) {
+        let processes = self.storage.get_all_active_sprocess().unwrap_or_default();
+        for (id, p) in processes {
+            self.suspended.insert(id);
+            if p.state == ProcessState::Disputed {
+                self.disputed.insert(id, Instant::now());
+            }
+            let requester_peer_id = p.requester_peer_id;
+            self.processes.insert(id, p);
+
+            // try re-establishing connection with requester.
+            // It is fine if it fails, since requester dials
+            // seeders it got disconnected from.
+            if let Err(e) = dial(&self.network_command_sender, requester_peer_id, None).await {
+                debug!(
+                    "(seeder) failed to dial requester {:?} of process {} with error {}",
+                    requester_peer_id, id, e
+                );
+            }
+        }
+    }
+
+    /// Announces files added to catalog on the DHT,
+    /// and stops announcing the removed ones
+    async fn sync_provider_records(&mut self) {
+        let root_hashes: HashSet<H256> = self.catalog.root_hashes().into_iter().collect();
+
+        for root_hash in self.provided.difference(&root_hashes) {
+            if let Err(e) = stop_providing(&self.network_command_sender, *root_hash).await {
+                error!(
+                    "(seeder) failed to stop providing {:?} with error {}",
+                    root_hash, e
+                );
+            }
+        }
+        self.provided.retain(|h| root_hashes.contains(h));
+
+        for root_hash in root_hashes {
+            if self.provided.contains(&root_hash) {
+                continue;
+            }
+            match start_providing(&self.network_command_sender, root_hash).await {
+                Ok(_) => {
+                    self.provided.insert(root_hash);
+                }
+                Err(e) => {
+                    error!(
+                        "(seeder) failed to provide {:?} with error {}",
+                        root_hash, e
+                    );
+                }
+            }
+        }
+    }
+
+    /// Shaper of upload bandwidth, for updating
+    /// limits and monitoring rates
+    pub fn bandwidth(&self) -> BandwidthShaper {
+        self.bandwidth.clone()
+    }
+
+    /// Priority of process for bandwidth. Requesters paying
+    /// more and with better reputation get more.
+    fn priority(&self, process: &SProcess) -> f64 {
+        (process.chunk_price.low_u128() as f64 + 1.0)
+            * self.reputations.standing(&process.requester_peer_id)
+    }
+
+    /// Records `event` of requester. Priorities of processes,
+    /// which depend on reputation, are updated.
+    fn record_reputation(&self, requester_peer_id: PeerId, event: ReputationEvent) {
+        self.reputations.record(requester_peer_id, event);
+        // other peers might have attested to the same address
+        for process in self.processes.values() {
+            self.bandwidth
+                .set_weight(process.id, self.priority(process));
+        }
+    }
+
+    /// Wakes up process `id` to make progress
+    fn wake(&self, id: u32) {
+        let _ = self.wake_sender.send(id);
+    }
+
+    /// Wakes up process `id` after `delay`
+    fn wake_after(&self, id: u32, delay: Duration) {
+        let wake_sender = self.wake_sender.clone();
+        tokio::spawn(async move {
+            time::sleep(delay).await;
+            let _ = wake_sender.send(id);
+        });
+    }
+
+    /// Returns process `process_id` of `requester_peer_id`
+    fn find_process(
+        &mut self,
+        requester_peer_id: PeerId,
+        process_id: u32,
+    ) -> anyhow::Result<&mut SProcess> {
+        let process = self
+            .processes
+            .get_mut(&process_id)
+            .ok_or_else(|| anyhow::anyhow!("Process {} does not exists", process_id))?;
+
+        if process.requester_peer_id != requester_peer_id {
+            return Err(anyhow::anyhow!(
+                "Process {} does not belong to {:?}",
+                process_id,
+                requester_peer_id
+            ));
+        }
+        Ok(process)
+    }
+
+    /// Calls `f` with quote context for `range` of chunks
+    /// of file with `root_hash` requested by `requester_address`
+    fn with_quote_context<T>(
+        &self,
+        requester_address: Address,
+        root_hash: &H256,
+        range: &Range<usize>,
+        f: impl FnOnce(&QuoteContext) -> T,
+    ) -> anyhow::Result<T> {
+        let file = self
+            .catalog
+            .get(root_hash)
+            .ok_or_else(|| anyhow::anyhow!("File {:?} not in catalog", root_hash))?;
+        // active receipt includes charges of RFPs
+        // that requester hasn't confirmed yet
+        let charged = self
+            .storage
+            .find_active_receipt(&requester_address)
+            .map(|r| r.receipt().owes(&requester_address))
+            .unwrap_or_default();
+        let unconfirmed = self
+            .processes
+            .values()
+            .filter(|p| p.requester_address == requester_address)
+            .filter_map(|p| {
+                p.pending_rfp
+                    .as_ref()
+                    .map(|pending| p.chunk_price * (pending.rfp_sequence_no - p.rfp_sequence_no))
+            })
+            .fold(U256::zero(), |sum, charge| sum + charge);
+        let requester_paid = charged.saturating_sub(unconfirmed);
+        let ctx = QuoteContext {
+            metadata: &file.metadata,
+            requester_address,
+            chunk_count: range.len(),
+            active_processes: self.processes.len(),
+            requester_paid,
+        };
+        Ok(f(&ctx))
+    }
+
+    /// Quote `quote_id` sent to requester
+    fn quote(
+        &self,
+        requester_peer_id: PeerId,
+        quote_id: u32,
+    ) -> anyhow::Result<FileExchangeResponse> {
+        let quote = self
+            .quotes
+            .get(&(requester_peer_id, quote_id))
+            .ok_or_else(|| anyhow::anyhow!("Quote {} does not exists", quote_id))?;
+        let size = self
+            .catalog
+            .get(&quote.root_hash)
+            .map(|f| f.metadata.size)
+            .unwrap_or_default();
+
+        Ok(FileExchangeResponse::Quote {
+            quote_id,
+            root_hash: quote.root_hash,
+            range: quote.range.clone(),
+            size,
+            terms: quote.terms.clone(),
+        })
+    }
+
+    /// Sends `request` of process `process_id` to requester.
+    /// Response is handled once it arrives.
+    async fn send_request(
+        &self,
+        requester_peer_id: PeerId,
+        process_id: u32,
+        request: FileExchangeRequest,
+    ) -> anyhow::Result<()> {
+        let sent = SentRequest::new(&request)?;
+        let (sender, receiver) = oneshot::channel();
+        self.network_command_sender
+            .send(Command::SendFileRequest {
+                peer_id: requester_peer_id,
+                request,
+                sender,
+            })
+            .await?;
+
+        let response_sender = self.response_sender.clone();
+        tokio::spawn(async move {
+            let response = match receiver.await {
+                Ok(response) => response,
+                Err(e) => Err(e.into()),
+            };
+            let _ = response_sender.send(RequesterResponse {
+                requester_peer_id,
+                process_id,
+                request: sent,
+                response,
+            });
+        });
+        Ok(())
+    }
+
+    /// Sends `request` of process `process_id` to
+    /// requester after `delay`, like `send_request`
+    fn send_request_after(
+        &self,
+        requester_peer_id: PeerId,
+        process_id: u32,
+        request: FileExchangeRequest,
+        delay: Duration,
+    ) -> anyhow::Result<()> {
+        let sent = SentRequest::new(&request)?;
+        let network_command_sender = self.network_command_sender.clone();
+        let response_sender = self.response_sender.clone();
+        tokio::spawn(async move {
+            time::sleep(delay).await;
+            let (sender, receiver) = oneshot::channel();
+            let response = match network_command_sender
+                .send(Command::SendFileRequest {
+                    peer_id: requester_peer_id,
+                    request,
+                    sender,
+                })
+                .await
+            {
+                Ok(()) => receiver.await.unwrap_or_else(|e| Err(e.into())),
+                Err(e) => Err(e.into()),
+            };
+            let _ = response_sender.send(RequesterResponse {
+                requester_peer_id,
+                process_id,
+                request: sent,
+                response,
+            });
+        });
+        Ok(())
+    }
+
+    /// Verifies attestation sent by requester and stores
+    /// the binding. Returns requester's address.
+    fn verify_attestation(
+        &self,
+        requester_peer_id: PeerId,
+        attestation: &Attestation,
+    ) -> anyhow::Result<Address> {
+        let address = attestation.verify(&requester_peer_id)?;
+        self.storage.store_peer_attestation(attestation)?;
+        Ok(address)
+    }
+
+    /// Checks that requester hasn't reached the
+    /// cap on concurrent processes
+    fn ensure_process_capacity(&self, requester_peer_id: PeerId) -> anyhow::Result<()> {
+        let active = self
+            .processes
+            .values()
+            .filter(|p| p.requester_peer_id == requester_peer_id)
+            .count();
+        if active >= MAX_PROCESSES_PER_REQUESTER {
+            return Err(anyhow::anyhow!(
+                "Too many active processes with requester {:?}",
+                requester_peer_id
+            ));
+        }
+        Ok(())
+    }
+
+    fn process_file_request(
+        &mut self,
+        requester_peer_id: PeerId,
+        requester_address: Address,
+        root_hash: H256,
+        range: Option<Range<usize>>,
+    ) -> anyhow::Result<FileExchangeResponse> {
+        self.ensure_process_capacity(requester_peer_id)?;
+
         // Check that file exists
-        // validate the merkle proof
-        // send request for `IWILLSEED`
+        let file = self
+            .catalog
+            .get(&root_hash)
+            .ok_or_else(|| anyhow::anyhow!("File {:?} not in catalog", root_hash))?;
+
+        // TODO: validate the merkle proof
+
+        let range = range.unwrap_or(0..file.chunk_count());
+        if range.start >= range.end || range.end > file.chunk_count() {
+            return Err(anyhow::anyhow!("Invalid chunk range {:?}", range));
+        }
+
+        let (chunk_price, payment_schedule) =
+            self.with_quote_context(requester_address, &root_hash, &range, |ctx| {
+                (
+                    self.pricing_policy.quote(ctx),
+                    self.pricing_policy.payment_schedule(ctx),
+                )
+            })?;
+
+        // quote id is used as id of the process
+        // once quote is accepted
+        let quote_id = self.storage.next_process_id()?;
+        self.quotes.insert(
+            (requester_peer_id, quote_id),
+            PendingQuote {
+                requester_address,
+                root_hash,
+                range,
+                quoted_price: chunk_price,
+                counters: 0,
+                terms: QuoteTerms {
+                    chunk_price,
+                    chunk_size: file.metadata.chunk_size,
+                    payment_schedule,
+                    valid_until: unix_timestamp() + QUOTE_VALIDITY.as_secs(),
+                },
+            },
+        );
+        self.quote(requester_peer_id, quote_id)
+    }
+
+    fn process_counter_offer(
+        &mut self,
+        requester_peer_id: PeerId,
+        quote_id: u32,
+        offered: U256,
+    ) -> anyhow::Result<FileExchangeResponse> {
+        let quote = self
+            .quotes
+            .get_mut(&(requester_peer_id, quote_id))
+            .ok_or_else(|| anyhow::anyhow!("Quote {} does not exists", quote_id))?;
+        quote.counters += 1;
+        if quote.counters > MAX_COUNTER_OFFERS {
+            self.quotes.remove(&(requester_peer_id, quote_id));
+            return Err(anyhow::anyhow!("Too many counter offers"));
+        }
+
+        let quote = &self.quotes[&(requester_peer_id, quote_id)];
+        let decision = self.with_quote_context(
+            quote.requester_address,
+            &quote.root_hash,
+            &quote.range,
+            |ctx| {
+                self.pricing_policy
+                    .counter(ctx, quote.quoted_price, offered)
+            },
+        )?;
+
+        let chunk_price = match decision {
+            QuoteDecision::Accept => offered,
+            QuoteDecision::Counter(price) => price,
+            QuoteDecision::Reject => {
+                self.quotes.remove(&(requester_peer_id, quote_id));
+                return Err(anyhow::anyhow!("Counter offer rejected"));
+            }
+        };
+
+        // respond with updated quote
+        if let Some(quote) = self.quotes.get_mut(&(requester_peer_id, quote_id)) {
+            quote.terms.chunk_price = chunk_price;
+            quote.terms.valid_until = unix_timestamp() + QUOTE_VALIDITY.as_secs();
+        }
+        self.quote(requester_peer_id, quote_id)
+    }
+
+    /// Starts process for the accepted quote
+    async fn process_quote_acceptance(
+        &mut self,
+        requester_peer_id: PeerId,
+        quote_id: u32,
+    ) -> anyhow::Result<()> {
+        let quote = self
+            .quotes
+            .remove(&(requester_peer_id, quote_id))
+            .ok_or_else(|| anyhow::anyhow!("Quote {} does not exists", quote_id))?;
+        if quote.terms.valid_until < unix_timestamp() {
+            return Err(anyhow::anyhow!("Quote {} has expired", quote_id));
+        }
+        // other quotes might have been accepted meanwhile
+        self.ensure_process_capacity(requester_peer_id)?;
+
+        let process = SProcess {
+            id: quote_id,
+            root_hash: quote.root_hash,
+            requester_address: quote.requester_address,
+            requester_peer_id,
+            sequence_no: quote.range.start,
+            end_sequence_no: quote.range.end,
+            rfp_sequence_no: quote.range.start,
+            chunk_price: quote.terms.chunk_price,
+            payment_schedule: quote.terms.payment_schedule,
+            pending_rfp: None,
+            state: ProcessState::Negotiating,
+        };
+        self.storage.update_active_sprocess(process.clone())?;
+        self.processes.insert(quote_id, process);
+
+        // send request for `IWILLSEED`. Transfer starts
+        // once requester acknowledges it.
+        let res = self
+            .send_request(
+                requester_peer_id,
+                quote_id,
+                FileExchangeRequest::IWillSeed {
+                    process_id: quote_id,
+                    attestation: self.attestation.clone(),
+                    root_hash: quote.root_hash,
+                },
+            )
+            .await;
+        if let Err(e) = res {
+            self.set_state(
+                quote_id,
+                ProcessState::Aborted {
+                    reason: format!("Failed to send IWillSeed: {}", e),
+                },
+            )?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Handles requester's response to a request of process
+    async fn process_requester_response(&mut self, response: RequesterResponse) {
+        let RequesterResponse {
+            requester_peer_id,
+            process_id,
+            request,
+            response,
+        } = response;
+        match request {
+            SentRequest::IWillSeed => {
+                if let Ok(FileExchangeResponse::RateLimited) = response {
+                    // sent again once requester accepts requests
+                    if let Err(e) = self.resend_iwillseed(requester_peer_id, process_id) {
+                        error!(
+                            "(seeder) failed to resend IWillSeed of process {} with error {}",
+                            process_id, e
+                        );
+                    }
+                    return;
+                }
+                let state = match response.and_then(|r| r.into_ack()) {
+                    Ok(_) => ProcessState::Transferring,
+                    Err(e) => ProcessState::Aborted {
+                        reason: format!("IWillSeed failed: {}", e),
+                    },
+                };
+                if let Err(e) = self.set_state(process_id, state) {
+                    error!(
+                        "(seeder) failed to start process {} with error {}",
+                        process_id, e
+                    );
+                }
+            }
+            SentRequest::Rfp => match response {
+                Ok(FileExchangeResponse::Receipt { receipt }) => {
+                    if let Err(e) =
+                        self.process_rfp_confirmation(requester_peer_id, process_id, receipt)
+                    {
+                        error!(
+                            "(seeder) invalid rfp confirmation for process {} with error {}",
+                            process_id, e
+                        );
+                    }
+                }
+                Ok(FileExchangeResponse::RateLimited) => {
+                    // requester is busy, not refusing to pay
+                    debug!("(seeder) rfp of process {} was rate limited", process_id);
+                    self.rfp_sent.remove(&process_id);
+                    self.wake_after(process_id, RETRY_DELAY);
+                }
+                Ok(response) => {
+                    // requester refuses to pay
+                    error!(
+                        "(seeder) rfp of process {} failed with error {}",
+                        process_id,
+                        response.into_error()
+                    );
+                    self.record_reputation(requester_peer_id, ReputationEvent::UnpaidRfp);
+                    if let Err(e) = self.set_state(process_id, ProcessState::Disputed) {
+                        error!(
+                            "(seeder) failed to halt process {} with error {}",
+                            process_id, e
+                        );
+                    }
+                }
+                Err(e) => {
+                    // RFP is sent again
+                    debug!(
+                        "(seeder) rfp of process {} failed with error {}",
+                        process_id, e
+                    );
+                    self.rfp_sent.remove(&process_id);
+                    self.wake_after(process_id, RETRY_DELAY);
+                }
+            },
+        }
     }
 
-    pub fn read_chunk_at_index(&self, index: usize) -> Option<&[u8]> {
-        self.main_file
-            .file
-            .chunks(self.main_file.chunk_size)
-            .nth(index)
+    /// Sends `IWillSeed` of process `process_id` again
+    /// after a delay, since requester rate limited it
+    fn resend_iwillseed(&self, requester_peer_id: PeerId, process_id: u32) -> anyhow::Result<()> {
+        let process = self
+            .processes
+            .get(&process_id)
+            .ok_or_else(|| anyhow::anyhow!("Process {} does not exists", process_id))?;
+        self.send_request_after(
+            requester_peer_id,
+            process_id,
+            FileExchangeRequest::IWillSeed {
+                process_id,
+                attestation: self.attestation.clone(),
+                root_hash: process.root_hash,
+            },
+            RETRY_DELAY,
+        )
+    }
+
+    /// Moves process `id` to `state` and stores it. Processes
+    /// that have ended are removed from active processes.
+    fn set_state(&mut self, id: u32, state: ProcessState) -> anyhow::Result<()> {
+        let process = self
+            .processes
+            .get_mut(&id)
+            .ok_or_else(|| anyhow::anyhow!("Process {} does not exists", id))?;
+        process.state.transition(state)?;
+        debug!("(seeder) process {} is {}", id, process.state);
+        if process.state == ProcessState::Disputed {
+            self.disputed.insert(id, Instant::now());
+        } else {
+            self.disputed.remove(&id);
+        }
+
+        if process.state.is_terminal() {
+            self.processes.remove(&id);
+            self.windows.remove(&id);
+            self.bandwidth.remove_process(id);
+            self.rfp_sent.remove(&id);
+            self.suspended.remove(&id);
+            self.storage.remove_active_sprocess(id)
+        } else {
+            self.storage.update_active_sprocess(process.clone())?;
+            self.wake(id);
+            Ok(())
+        }
     }
 
-    pub fn send_chunk(&self, process: SProcess) {
-        let chunk = self
-            .read_chunk_at_index(process.sequence_no)
-            .expect("Chunk should be present")
-            .to_owned();
+    /// Resumes process `process_id` from `sequence_no`, the
+    /// index of the next chunk requester expects. `receipt` is
+    /// the latest receipt signed by requester.
+    fn process_resume(
+        &mut self,
+        requester_peer_id: PeerId,
+        process_id: u32,
+        sequence_no: usize,
+        receipt: Option<ReceiptWithSignatures>,
+    ) -> anyhow::Result<()> {
+        let suspended = self.suspended.contains(&process_id);
+        let storage = self.storage.clone();
+        let process = self.find_process(requester_peer_id, process_id)?;
+
+        if process.state.is_terminal() {
+            return Err(anyhow::anyhow!("Process {} has ended", process_id));
+        }
+
+        // Requester might have signed pending RFP, but
+        // confirmation never reached us.
+        if let (Some(pending), Some(receipt)) = (&process.pending_rfp, &receipt) {
+            if pending.receipt.receipt() == receipt.receipt() {
+                storage.store_active_receipt(&process.requester_address, receipt)?;
+                process.rfp_sequence_no = pending.rfp_sequence_no;
+                process.pending_rfp = None;
+            }
+        }
+
+        // Requester can't be ahead of us. If it is behind
+        // (i.e. chunks were lost), rewind. Rewinding does not
+        // affect `rfp_sequence_no`, thus re-sent chunks that were
+        // already paid for aren't charged again.
+        // Sequence no. isn't stored for every chunk sent, thus
+        // for recovered processes requester can be ahead of the
+        // stored one. Chunks it skips are still charged for.
+        let max_sequence_no = if suspended {
+            process.end_sequence_no
+        } else {
+            process.sequence_no
+        };
+        if sequence_no > max_sequence_no {
+            return Err(anyhow::anyhow!(
+                "Requester is ahead of process {}",
+                process_id
+            ));
+        }
+        process.sequence_no = sequence_no;
+        let state = if process.pending_rfp.is_some() {
+            ProcessState::AwaitingPayment
+        } else {
+            ProcessState::Transferring
+        };
+        if process.state != state {
+            process.state.transition(state)?;
+        }
+        let process = process.clone();
 
-        // TODO:
-        // 1. send the chunk to the `address` in `process`
-        // 2. increase process sequence number by 1 and store
-        // 3. Also check whether it was the end.
+        // resend pending RFP, if any
+        self.rfp_sent.remove(&process_id);
+        self.windows
+            .insert(process_id, SlidingWindow::new(sequence_no));
+
+        self.storage.update_active_sprocess(process)?;
+        self.suspended.remove(&process_id);
+        self.disputed.remove(&process_id);
+        self.wake(process_id);
+        Ok(())
     }
 
-    pub fn send_rfp(&self) {}
+    /// Shrinks range of process to end at `end_sequence_no`
+    /// (exclusive). Range can't be extended, since its price
+    /// was agreed on, and chunks that have already been received
+    /// or paid for can't be excluded. Chunks in flight beyond
+    /// the new end are dropped by requester.
+    fn process_range_update(
+        &mut self,
+        requester_peer_id: PeerId,
+        process_id: u32,
+        end_sequence_no: usize,
+    ) -> anyhow::Result<()> {
+        let acked = self.windows.get(&process_id).map(|w| w.acked());
+        let process = self.find_process(requester_peer_id, process_id)?;
+
+        if end_sequence_no > process.end_sequence_no {
+            return Err(anyhow::anyhow!(
+                "Range of process {} can't be extended to {}",
+                process_id,
+                end_sequence_no
+            ));
+        }
+        let min_end_sequence_no = acked
+            .unwrap_or(process.sequence_no)
+            .max(process.rfp_sequence_no);
+        if end_sequence_no < min_end_sequence_no {
+            return Err(anyhow::anyhow!(
+                "Chunks of process {} till {} were received or paid for",
+                process_id,
+                min_end_sequence_no
+            ));
+        }
+        process.end_sequence_no = end_sequence_no;
+        let process = process.clone();
+
+        self.storage.update_active_sprocess(process)?;
+        self.wake(process_id);
+        Ok(())
+    }
+
+    /// Sends chunk at sequence no. of `process` without
+    /// waiting for requester to receive it. Progress isn't
+    /// stored, since requester tells us the chunk it expects
+    /// on resume.
+    pub async fn send_chunk(&self, process: &mut SProcess) -> anyhow::Result<()> {
+        let chunk = match self
+            .chunk_store
+            .read_chunk(&process.root_hash, process.sequence_no)
+        {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => {
+                return Err(anyhow::anyhow!(
+                    "Chunk {} of file {:?} not found",
+                    process.sequence_no,
+                    process.root_hash
+                ));
+            }
+            Err(e) => return Err(e),
+        };
+        let proof = self
+            .catalog
+            .get(&process.root_hash)
+            .and_then(|file| file.proof(process.sequence_no))
+            .ok_or_else(|| anyhow::anyhow!("File {:?} not in catalog", process.root_hash))?;
+
+        send_chunk(
+            &self.network_command_sender,
+            process.requester_peer_id,
+            Chunk {
+                process_id: process.id,
+                sequence_no: process.sequence_no,
+                data: chunk.to_vec(),
+                proof,
+            },
+        )
+        .await?;
+
+        process.sequence_no += 1;
+        Ok(())
+    }
+
+    /// Sends RFP for chunks till `rfp_sequence_no` (exclusive)
+    pub async fn send_rfp(
+        &mut self,
+        process: &mut SProcess,
+        rfp_sequence_no: usize,
+    ) -> anyhow::Result<()> {
+        let pending = match process.pending_rfp.clone() {
+            Some(pending) => pending,
+            None => {
+                // receipts are only issued to authenticated addresses
+                self.storage
+                    .ensure_peer_address(&process.requester_peer_id, &process.requester_address)?;
+                let unpaid = rfp_sequence_no - process.rfp_sequence_no;
+                let receipt = self.wallet.process_outgoing_rfp(
+                    process.requester_address,
+                    process.chunk_price * unpaid,
+                )?;
+
+                // store pending RFP before sending it
+                let pending = PendingRfp {
+                    receipt,
+                    rfp_sequence_no,
+                };
+                process.state.transition(ProcessState::AwaitingPayment)?;
+                process.pending_rfp = Some(pending.clone());
+                self.storage.update_active_sprocess(process.clone())?;
+                pending
+            }
+        };
+
+        self.send_request(
+            process.requester_peer_id,
+            process.id,
+            FileExchangeRequest::Rfp {
+                process_id: process.id,
+                rfp_sequence_no: pending.rfp_sequence_no,
+                receipt: pending.receipt,
+            },
+        )
+        .await?;
+        self.rfp_sent.insert(process.id);
+        Ok(())
+    }
+
+    /// Marks pending RFP of process as paid
+    fn process_rfp_confirmation(
+        &mut self,
+        requester_peer_id: PeerId,
+        process_id: u32,
+        receipt: ReceiptWithSignatures,
+    ) -> anyhow::Result<()> {
+        let process = self.find_process(requester_peer_id, process_id)?;
+        if process.state != ProcessState::AwaitingPayment {
+            return Err(anyhow::anyhow!(
+                "Process {} isn't awaiting payment",
+                process_id
+            ));
+        }
+
+        // Validate that RFP matches with the one sent
+        // TODO: validate signatures
+        let rfp_sequence_no = match &process.pending_rfp {
+            Some(pending) if pending.receipt.receipt() == receipt.receipt() => {
+                pending.rfp_sequence_no
+            }
+            _ => {
+                self.record_reputation(requester_peer_id, ReputationEvent::InvalidReceipt);
+                self.set_state(process_id, ProcessState::Disputed)?;
+                return Err(anyhow::anyhow!("Receipt does not match pending RFP"));
+            }
+        };
+
+        process.state.transition(ProcessState::Transferring)?;
+        process.pending_rfp = None;
+        process.rfp_sequence_no = rfp_sequence_no;
+        let process = process.clone();
+
+        self.storage
+            .store_active_receipt(&process.requester_address, &receipt)?;
+        self.storage.update_active_sprocess(process)?;
+        self.rfp_sent.remove(&process_id);
+        self.wake(process_id);
+        Ok(())
+    }
+
+    /// Moves window of process forward on
+    /// cumulative ack of chunks before `sequence_no`
+    fn process_chunk_ack(
+        &mut self,
+        requester_peer_id: PeerId,
+        process_id: u32,
+        sequence_no: usize,
+    ) -> anyhow::Result<()> {
+        let process = self.find_process(requester_peer_id, process_id)?;
+        let sequence_no = sequence_no.min(process.end_sequence_no);
+        // chunks sent before going back might be
+        // acked after it
+        process.sequence_no = process.sequence_no.max(sequence_no);
+        let start = process.sequence_no;
+
+        let window = self
+            .windows
+            .entry(process_id)
+            .or_insert_with(|| SlidingWindow::new(start));
+        if window.on_ack(sequence_no) {
+            self.wake(process_id);
+        }
+        Ok(())
+    }
+
+    /// Goes back to the first unacked chunk for
+    /// processes whose chunks in flight timed out
+    fn check_ack_timeouts(&mut self) {
+        for (id, window) in self.windows.iter_mut() {
+            if !window.is_timed_out() {
+                continue;
+            }
+            if let Some(process) = self.processes.get_mut(id) {
+                process.sequence_no = window.on_loss();
+                debug!(
+                    "(seeder) chunks of process {} lost, window shrunk to {}",
+                    id,
+                    window.window()
+                );
+                let _ = self.wake_sender.send(*id);
+            }
+        }
+    }
+
+    /// Aborts disputed processes that requesters
+    /// haven't resumed within `DISPUTE_TIMEOUT`, so
+    /// that they don't count towards their cap
+    async fn abort_disputed(&mut self) {
+        let expired: Vec<u32> = self
+            .disputed
+            .iter()
+            .filter(|(_, at)| at.elapsed() >= DISPUTE_TIMEOUT)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            let state = ProcessState::Aborted {
+                reason: "Dispute wasn't resolved".into(),
+            };
+            if let Err(e) = self.end_process(id, state).await {
+                error!(
+                    "(seeder) failed to abort disputed process {} with error {}",
+                    id, e
+                );
+            }
+        }
+    }
+
+    /// Moves process `id` to terminal `state`
+    /// and closes its chunk stream
+    async fn end_process(&mut self, id: u32, state: ProcessState) -> anyhow::Result<()> {
+        let requester_peer_id = self
+            .processes
+            .get(&id)
+            .map(|p| p.requester_peer_id)
+            .ok_or_else(|| anyhow::anyhow!("Process {} does not exists", id))?;
+        self.set_state(id, state)?;
+        close_chunk_stream(&self.network_command_sender, requester_peer_id, id).await
+    }
+
+    /// Makes progress on process `id` by sending either
+    /// the next chunk or an RFP, whichever is due. Returns
+    /// whether process can make more progress right away.
+    async fn drive(&mut self, id: u32) -> anyhow::Result<bool> {
+        if self.suspended.contains(&id) || self.rfp_sent.contains(&id) {
+            return Ok(false);
+        }
+        match self.processes.get(&id).map(|p| &p.state) {
+            Some(ProcessState::Transferring | ProcessState::AwaitingPayment) => {}
+            _ => return Ok(false),
+        }
+
+        let (chunk_size, size) = match self.catalog.get(&self.processes[&id].root_hash) {
+            Some(f) => (f.metadata.chunk_size, f.metadata.size),
+            None => {
+                self.end_process(
+                    id,
+                    ProcessState::Aborted {
+                        reason: "File not in catalog".into(),
+                    },
+                )
+                .await?;
+                return Ok(false);
+            }
+        };
+        let chunk_len = |i: usize| chunk_size.min(size.saturating_sub(i * chunk_size));
+
+        let start = self.processes[&id].sequence_no;
+        let acked = self
+            .windows
+            .entry(id)
+            .or_insert_with(|| SlidingWindow::new(start))
+            .acked();
+        if acked >= self.processes[&id].end_sequence_no
+            && self.processes[&id].rfp_sequence_no >= self.processes[&id].end_sequence_no
+        {
+            // all chunks sent and paid for
+            self.record_reputation(
+                self.processes[&id].requester_peer_id,
+                ReputationEvent::CompletedTransfer,
+            );
+            self.end_process(id, ProcessState::Completed).await?;
+            return Ok(false);
+        }
+
+        let mut process = match self.processes.remove(&id) {
+            Some(process) => process,
+            None => return Ok(false),
+        };
+
+        // Pending RFP is sent again as is. Post paid, only
+        // chunks requester has acked are charged for.
+        let charged_till = match process.payment_schedule {
+            PaymentSchedule::Prepaid { .. } => process.sequence_no,
+            _ => acked,
+        };
+        let rfp_due = match &process.pending_rfp {
+            Some(pending) => Some(pending.rfp_sequence_no),
+            None => process.payment_schedule.rfp_due(
+                charged_till,
+                process.rfp_sequence_no,
+                process.end_sequence_no,
+                chunk_len,
+            ),
+        };
+        let res = if let Some(rfp_sequence_no) = rfp_due {
+            // wait for confirmation after sending RFP
+            self.send_rfp(&mut process, rfp_sequence_no)
+                .await
+                .map(|_| false)
+        } else if process.sequence_no < process.end_sequence_no
+            && process.payment_schedule.can_send(
+                process.sequence_no,
+                process.rfp_sequence_no,
+                chunk_len,
+            )
+            && self.windows.get(&id).is_some_and(|w| w.can_send())
+        {
+            if !self.bandwidth.has_process(id) {
+                self.bandwidth
+                    .add_process(id, process.requester_peer_id, self.priority(&process));
+            }
+            if let Err(wait) =
+                self.bandwidth
+                    .acquire(id, chunk_len(process.sequence_no), Instant::now())
+            {
+                // woken up again once bandwidth is available
+                self.processes.insert(id, process);
+                self.wake_after(id, wait);
+                return Ok(false);
+            }
+
+            // send chunk at sequence no.
+            let res = self.send_chunk(&mut process).await;
+            if res.is_ok() {
+                if let Some(window) = self.windows.get_mut(&id) {
+                    window.on_send();
+                }
+            }
+            res.map(|_| true)
+        } else {
+            Ok(false)
+        };
+
+        self.processes.insert(id, process);
+        res
+    }
 
     pub async fn run(&mut self) {
-        let mut interval = time::interval(time::Duration::from_secs(5));
+        self.recover_processes().await;
+
+        let mut interval = time::interval(QUOTE_VALIDITY);
+        let mut ack_interval = time::interval(ACK_TIMEOUT / 5);
+        let mut catalog_interval = time::interval(CATALOG_SYNC_INTERVAL);
         loop {
             select! {
+                _ = catalog_interval.tick() => {
+                    self.sync_provider_records().await;
+                },
+                _ = ack_interval.tick() => {
+                    self.check_ack_timeouts();
+                },
                 _ = interval.tick() => {
-                    if let Ok(processes) = self.storage.get_all_active_sprocess() {
-                        for (_,p) in processes {
-                            if p.sequence_no < p.rfp_sequence_no + 1 {
-                                // send chunk at sequence no.
-                                self.send_chunk(p);
-                            }else {
-                                // send RFP
-                            }
-
-                            // Check what action to take depending on the status
-                            // Send RFP?
-                            // Send byte?
+                    // drop expired quotes
+                    let now = unix_timestamp();
+                    self.quotes.retain(|_, q| q.terms.valid_until >= now);
+                    self.abort_disputed().await;
+                },
+                Some(response) = self.response_receiver.recv() => {
+                    self.process_requester_response(response).await;
+                },
+                Some(id) = self.wake_receiver.recv() => {
+                    match self.drive(id).await {
+                        Ok(true) => self.wake(id),
+                        Ok(false) => {}
+                        Err(e) => {
+                            error!("(seeder) process {} failed with error {}", id, e);
+                            self.wake_after(id, RETRY_DELAY);
                         }
-                    };
-
+                    }
                 },
-                event = self.network_event_receiver.recv() => {
-                    if let Ok(event) = event {
-                        self.handle_network_event(event).await;
+                event = self.network_event_receiver.recv() => match event {
+                    Ok(event) => self.handle_network_event(event).await,
+                    // lost acks are covered by ack timeouts, and
+                    // requesters retry requests that weren't
+                    // responded to
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("(seeder) missed {} network events", n);
                     }
+                    Err(RecvError::Closed) => return,
                 }
             }
         }
     }
 
-    async fn handle_network_event(&self, event: NetworkEvent) {
+    async fn handle_network_event(&mut self, event: NetworkEvent) {
         match event {
+            // left to requester running on the same network
+            NetworkEvent::FileExchangeRequest { request, .. } if request.is_for_requester() => {}
             NetworkEvent::FileExchangeRequest {
                 sender_peer_id,
                 request_id,
                 request,
-            } => match request {
-                FileExchangeRequest::IWant { self_address } => {
-                    self.process_file_request(self_address);
+            } => {
+                let response = self
+                    .handle_request(sender_peer_id, request)
+                    .await
+                    .unwrap_or_else(|e| FileExchangeResponse::rejected(&e));
+                if let Err(e) = send_file_response(
+                    &self.network_command_sender,
+                    sender_peer_id,
+                    request_id,
+                    response,
+                )
+                .await
+                {
+                    error!(
+                        "(seeder) failed to respond to {:?} with error {}",
+                        sender_peer_id, e
+                    );
                 }
-                FileExchangeRequest::RfpC {
-                    process_id,
-                    receipt,
-                } => {
-                    // Received confirmation for rfp. Validate that RFP matches with the one sent (and is signed),
-                    // after which progress further.
+            }
+            NetworkEvent::ChunkReceived { .. }
+            | NetworkEvent::FileExchangeOutboundFailure { .. }
+            | NetworkEvent::FileExchangeInboundFailure { .. }
+            | NetworkEvent::CodecError { .. }
+            | NetworkEvent::PeerConnected { .. }
+            | NetworkEvent::PeerDiscovered { .. }
+            | NetworkEvent::NatStatusChanged { .. } => {}
+        }
+    }
+
+    /// Handles request from requester and
+    /// returns the response to it
+    async fn handle_request(
+        &mut self,
+        sender_peer_id: PeerId,
+        request: FileExchangeRequest,
+    ) -> anyhow::Result<FileExchangeResponse> {
+        // file requests are checked once their attestation is
+        // verified, to reject peers attesting to banned addresses
+        if !matches!(request, FileExchangeRequest::IWant { .. })
+            && self.reputations.is_banned(&sender_peer_id)
+        {
+            return Err(anyhow::anyhow!("Peer is banned"));
+        }
+
+        match request {
+            FileExchangeRequest::IWant {
+                attestation,
+                root_hash,
+                range,
+            } => {
+                let res = self
+                    .verify_attestation(sender_peer_id, &attestation)
+                    .and_then(|requester_address| {
+                        if self.reputations.is_banned(&sender_peer_id)
+                            || self.reputations.is_address_banned(&requester_address)
+                        {
+                            return Err(anyhow::anyhow!("Peer is banned"));
+                        }
+                        self.process_file_request(
+                            sender_peer_id,
+                            requester_address,
+                            root_hash,
+                            range,
+                        )
+                    });
+                if let Err(e) = &res {
+                    debug!(
+                        "(seeder) rejected file request from {:?} with error {}",
+                        sender_peer_id, e
+                    );
                 }
-                _ => {}
-            },
+                res
+            }
+            FileExchangeRequest::CounterOffer {
+                quote_id,
+                chunk_price,
+            } => {
+                let res = self.process_counter_offer(sender_peer_id, quote_id, chunk_price);
+                if let Err(e) = &res {
+                    debug!(
+                        "(seeder) counter offer for quote {} failed with error {}",
+                        quote_id, e
+                    );
+                }
+                res
+            }
+            FileExchangeRequest::Accept { quote_id } => {
+                let res = self
+                    .process_quote_acceptance(sender_peer_id, quote_id)
+                    .await;
+                if let Err(e) = &res {
+                    error!(
+                        "(seeder) failed to start process for quote {} with error {}",
+                        quote_id, e
+                    );
+                }
+                res.map(|_| FileExchangeResponse::Ack)
+            }
+            FileExchangeRequest::ChunkAck {
+                process_id,
+                sequence_no,
+            } => {
+                let res = self.process_chunk_ack(sender_peer_id, process_id, sequence_no);
+                if let Err(e) = &res {
+                    debug!(
+                        "(seeder) dropped ack of process {} with error {}",
+                        process_id, e
+                    );
+                }
+                res.map(|_| FileExchangeResponse::Ack)
+            }
+            FileExchangeRequest::UpdateRange {
+                process_id,
+                end_sequence_no,
+            } => {
+                let res = self.process_range_update(sender_peer_id, process_id, end_sequence_no);
+                if let Err(e) = &res {
+                    error!(
+                        "(seeder) failed to update range of process {} with error {}",
+                        process_id, e
+                    );
+                }
+                res.map(|_| FileExchangeResponse::Ack)
+            }
+            FileExchangeRequest::Resume {
+                process_id,
+                sequence_no,
+                receipt,
+            } => {
+                let res = self.process_resume(sender_peer_id, process_id, sequence_no, receipt);
+                if let Err(e) = &res {
+                    error!(
+                        "(seeder) failed to resume process {} with error {}",
+                        process_id, e
+                    );
+                }
+                res.map(|_| FileExchangeResponse::Ack)
+            }
+            FileExchangeRequest::IWillSeed { .. } | FileExchangeRequest::Rfp { .. } => {
+                Err(anyhow::anyhow!("Request is for requester"))
+            }
         }
     }
 }
@@ -0,0 +1,193 @@
+use std::collections::VecDeque;
+use tokio::time::{Duration, Instant};
+
+/// No. of chunks in flight a transfer starts with
+pub const INITIAL_WINDOW: usize = 4;
+pub const MAX_WINDOW: usize = 64;
+/// Time without any ack after which chunks
+/// in flight are considered lost
+pub const ACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sliding window over chunks sent to requester.
+///
+/// Requester acknowledges cumulatively, i.e. ack of `n` means
+/// all chunks before `n` have been received. Window grows by
+/// one chunk for every timely ack, shrinks by one for every
+/// slow ack (i.e. RTT over twice the smoothed RTT), and halves
+/// when chunks are lost.
+pub struct SlidingWindow {
+    /// Index of the first chunk that hasn't been acked
+    acked: usize,
+    /// Max no. of chunks in flight
+    window: usize,
+    /// Send time of chunks in flight, starting from `acked`
+    sent_at: VecDeque<Instant>,
+    /// Smoothed RTT
+    srtt: Option<Duration>,
+    /// Time window last moved forward
+    last_progress_at: Instant,
+}
+
+impl SlidingWindow {
+    pub fn new(acked: usize) -> Self {
+        Self {
+            acked,
+            window: INITIAL_WINDOW,
+            sent_at: VecDeque::new(),
+            srtt: None,
+            last_progress_at: Instant::now(),
+        }
+    }
+
+    pub fn acked(&self) -> usize {
+        self.acked
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.sent_at.len()
+    }
+
+    /// Whether another chunk can be sent
+    pub fn can_send(&self) -> bool {
+        self.in_flight() < self.window
+    }
+
+    /// Records chunk at `acked + in_flight` as sent
+    pub fn on_send(&mut self) {
+        if self.sent_at.is_empty() {
+            self.last_progress_at = Instant::now();
+        }
+        self.sent_at.push_back(Instant::now());
+    }
+
+    /// Records cumulative ack of chunks before `sequence_no`.
+    /// Returns whether window moved forward.
+    pub fn on_ack(&mut self, sequence_no: usize) -> bool {
+        if sequence_no <= self.acked {
+            return false;
+        }
+
+        let newly_acked = sequence_no - self.acked;
+        let mut latest_sent_at = None;
+        for _ in 0..newly_acked {
+            if let Some(sent_at) = self.sent_at.pop_front() {
+                latest_sent_at = Some(sent_at);
+            }
+        }
+        self.acked = sequence_no;
+        self.last_progress_at = Instant::now();
+
+        if let Some(sent_at) = latest_sent_at {
+            let rtt = sent_at.elapsed();
+            let slow = self.srtt.is_some_and(|srtt| rtt > srtt * 2);
+            self.srtt = Some(match self.srtt {
+                Some(srtt) => (srtt * 7 + rtt) / 8,
+                None => rtt,
+            });
+
+            if slow {
+                self.window = (self.window - 1).max(1);
+            } else {
+                self.window = (self.window + 1).min(MAX_WINDOW);
+            }
+        }
+        true
+    }
+
+    /// Whether chunks in flight haven't been
+    /// acked for longer than `ACK_TIMEOUT`
+    pub fn is_timed_out(&self) -> bool {
+        !self.sent_at.is_empty() && self.last_progress_at.elapsed() >= ACK_TIMEOUT
+    }
+
+    /// Treats chunks in flight as lost. Returns index of the
+    /// chunk from which sending should start again.
+    pub fn on_loss(&mut self) -> usize {
+        self.window = (self.window / 2).max(1);
+        self.sent_at.clear();
+        self.last_progress_at = Instant::now();
+        self.acked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time;
+
+    /// Sends chunks till window is full
+    fn fill(window: &mut SlidingWindow) {
+        while window.can_send() {
+            window.on_send();
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn window_grows_on_timely_acks() {
+        let mut window = SlidingWindow::new(10);
+        fill(&mut window);
+        assert_eq!(window.in_flight(), INITIAL_WINDOW);
+
+        // one cumulative ack for all chunks in flight
+        assert!(window.on_ack(10 + INITIAL_WINDOW));
+        assert_eq!(window.acked(), 10 + INITIAL_WINDOW);
+        assert_eq!(window.in_flight(), 0);
+        assert_eq!(window.window(), INITIAL_WINDOW + 1);
+
+        // stale and duplicate acks are ignored
+        assert!(!window.on_ack(10));
+        assert!(!window.on_ack(10 + INITIAL_WINDOW));
+        assert_eq!(window.window(), INITIAL_WINDOW + 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn window_shrinks_on_slow_acks() {
+        let mut window = SlidingWindow::new(0);
+        window.on_send();
+        time::advance(Duration::from_millis(10)).await;
+        window.on_ack(1);
+        assert_eq!(window.window(), INITIAL_WINDOW + 1);
+
+        window.on_send();
+        time::advance(Duration::from_millis(100)).await;
+        window.on_ack(2);
+        assert_eq!(window.window(), INITIAL_WINDOW);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn window_halves_on_loss() {
+        let mut window = SlidingWindow::new(5);
+        fill(&mut window);
+        window.on_ack(7);
+        let grown = window.window();
+        assert!(!window.is_timed_out());
+
+        time::advance(ACK_TIMEOUT).await;
+        assert!(window.is_timed_out());
+        assert_eq!(window.on_loss(), 7);
+        assert_eq!(window.window(), grown / 2);
+        assert_eq!(window.in_flight(), 0);
+        assert!(!window.is_timed_out());
+    }
+
+    #[test]
+    fn window_stays_within_bounds() {
+        let mut window = SlidingWindow::new(0);
+        for _ in 0..8 {
+            window.on_loss();
+        }
+        assert_eq!(window.window(), 1);
+        window.on_send();
+        assert!(!window.can_send());
+
+        for i in 1..=2 * MAX_WINDOW {
+            window.on_ack(i);
+            window.on_send();
+        }
+        assert_eq!(window.window(), MAX_WINDOW);
+    }
+}
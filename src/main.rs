@@ -1,6 +1,16 @@
-mod network;
+mod bandwidth;
+mod catalog;
+mod chunk_store;
+mod file_requester;
 mod file_seeder;
+mod flow_control;
+mod network;
+mod pricing;
+mod process_state;
+mod reputation;
 mod storage;
+#[cfg(test)]
+mod test_harness;
 mod wallet;
 
 fn main() {
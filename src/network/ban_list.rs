@@ -0,0 +1,53 @@
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Ban imposed on a peer
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Ban {
+    /// Banned till unix timestamp (in secs)
+    Until(u64),
+    Permanent,
+}
+
+impl Ban {
+    pub fn is_active(&self, now: u64) -> bool {
+        match self {
+            Ban::Until(until) => now < *until,
+            Ban::Permanent => true,
+        }
+    }
+}
+
+/// Peers banned by the application. Network refuses
+/// connections with them. Bans expire lazily.
+#[derive(Clone, Default)]
+pub struct BanList {
+    bans: Arc<Mutex<HashMap<PeerId, Ban>>>,
+}
+
+impl BanList {
+    pub fn ban(&self, peer_id: PeerId, ban: Ban) {
+        self.bans.lock().unwrap().insert(peer_id, ban);
+    }
+
+    pub fn is_banned(&self, peer_id: &PeerId) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut bans = self.bans.lock().unwrap();
+        match bans.get(peer_id) {
+            Some(ban) if ban.is_active(now) => true,
+            Some(_) => {
+                bans.remove(peer_id);
+                false
+            }
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,525 @@
+use futures::{
+    channel::mpsc,
+    future::{self, BoxFuture},
+    stream::{self, BoxStream, FuturesUnordered, SelectAll},
+    AsyncReadExt, AsyncWriteExt, FutureExt, StreamExt,
+};
+use libp2p::{
+    core::{
+        connection::ConnectionId,
+        upgrade::{read_length_prefixed, write_length_prefixed},
+        ConnectedPoint, InboundUpgrade, OutboundUpgrade, UpgradeInfo,
+    },
+    swarm::{
+        ConnectionHandler, ConnectionHandlerEvent, ConnectionHandlerUpgrErr, KeepAlive,
+        NegotiatedSubstream, NetworkBehaviour, NetworkBehaviourAction, NotifyHandler,
+        PollParameters, SubstreamProtocol,
+    },
+    PeerId,
+};
+use log::debug;
+use std::{
+    collections::{HashMap, VecDeque},
+    io, iter,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+pub const CHUNK_STREAM_PROTOCOL_ID: &[u8] = b"/dse/chunk-stream/0.1";
+
+/// Max size of a chunk accepted over the stream
+pub const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+/// Max no. of hashes in merkle proof of a chunk,
+/// i.e. depth of the merkle tree of a file
+const MAX_PROOF_LEN: usize = 64;
+
+/// Max no. of chunks queued per outbound stream. Seeder's
+/// flow control keeps fewer chunks in flight, thus chunks
+/// beyond it are dropped and sent again on loss.
+const STREAM_BUFFER: usize = 64;
+
+/// Max no. of inbound streams per connection. No. of
+/// connections per peer is capped by the swarm.
+const MAX_INBOUND_STREAMS: usize = 32;
+
+/// Chunk of a file sent as part of a process
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub process_id: u32,
+    pub sequence_no: usize,
+    pub data: Vec<u8>,
+    /// Merkle proof of chunk against
+    /// root hash of the file
+    pub proof: Vec<[u8; 32]>,
+}
+
+#[derive(Debug)]
+pub enum ChunkStreamEvent {
+    Received { peer_id: PeerId, chunk: Chunk },
+}
+
+/// Streams chunks of a process over a dedicated substream.
+///
+/// A substream is opened per process and starts with the
+/// process id (u32 BE). It is followed by chunks, each one
+/// being its sequence no. (u64 BE), length-prefixed merkle
+/// proof and length-prefixed data.
+/// Seeder closes the substream once the process ends.
+///
+/// Inbound substreams are only accepted for processes whose
+/// chunks are expected, and chunks are read up to the chunk
+/// size of the process.
+#[derive(Default)]
+pub struct ChunkStream {
+    connections: HashMap<PeerId, Vec<ConnectionId>>,
+    /// Chunk sizes of processes whose
+    /// chunks are expected from peers
+    expected: HashMap<PeerId, HashMap<u32, usize>>,
+    actions: VecDeque<NetworkBehaviourAction<ChunkStreamEvent, ChunkStreamHandler>>,
+}
+
+impl ChunkStream {
+    /// Queues `chunk` to be sent to `peer_id` on
+    /// the substream of its process
+    pub fn send_chunk(&mut self, peer_id: &PeerId, chunk: Chunk) -> anyhow::Result<()> {
+        let connection = self
+            .connections
+            .get(peer_id)
+            .and_then(|c| c.first())
+            .ok_or_else(|| anyhow::anyhow!("Not connected to {:?}", peer_id))?;
+        self.actions
+            .push_back(NetworkBehaviourAction::NotifyHandler {
+                peer_id: *peer_id,
+                handler: NotifyHandler::One(*connection),
+                event: HandlerIn::Send(chunk),
+            });
+        Ok(())
+    }
+
+    /// Accepts chunks of process `process_id` from `peer_id` of
+    /// up to `chunk_size` bytes, or stops accepting them if `None`
+    pub fn expect_chunks(&mut self, peer_id: &PeerId, process_id: u32, chunk_size: Option<usize>) {
+        let expected = self.expected.entry(*peer_id).or_default();
+        match chunk_size {
+            Some(chunk_size) => expected.insert(process_id, chunk_size),
+            None => expected.remove(&process_id),
+        };
+        if expected.is_empty() {
+            self.expected.remove(peer_id);
+        }
+
+        for connection in self.connections.get(peer_id).into_iter().flatten() {
+            self.actions
+                .push_back(NetworkBehaviourAction::NotifyHandler {
+                    peer_id: *peer_id,
+                    handler: NotifyHandler::One(*connection),
+                    event: HandlerIn::Expect {
+                        process_id,
+                        chunk_size,
+                    },
+                });
+        }
+    }
+
+    /// Closes substream of process `process_id` with `peer_id`
+    pub fn close_stream(&mut self, peer_id: &PeerId, process_id: u32) {
+        if let Some(connection) = self.connections.get(peer_id).and_then(|c| c.first()) {
+            self.actions
+                .push_back(NetworkBehaviourAction::NotifyHandler {
+                    peer_id: *peer_id,
+                    handler: NotifyHandler::One(*connection),
+                    event: HandlerIn::Close { process_id },
+                });
+        }
+    }
+}
+
+impl NetworkBehaviour for ChunkStream {
+    type ConnectionHandler = ChunkStreamHandler;
+    type OutEvent = ChunkStreamEvent;
+
+    fn new_handler(&mut self) -> Self::ConnectionHandler {
+        ChunkStreamHandler::default()
+    }
+
+    fn inject_connection_established(
+        &mut self,
+        peer_id: &PeerId,
+        connection_id: &ConnectionId,
+        _: &ConnectedPoint,
+        _: Option<&Vec<libp2p::Multiaddr>>,
+        _: usize,
+    ) {
+        self.connections
+            .entry(*peer_id)
+            .or_default()
+            .push(*connection_id);
+
+        for (process_id, chunk_size) in self.expected.get(peer_id).into_iter().flatten() {
+            self.actions
+                .push_back(NetworkBehaviourAction::NotifyHandler {
+                    peer_id: *peer_id,
+                    handler: NotifyHandler::One(*connection_id),
+                    event: HandlerIn::Expect {
+                        process_id: *process_id,
+                        chunk_size: Some(*chunk_size),
+                    },
+                });
+        }
+    }
+
+    fn inject_connection_closed(
+        &mut self,
+        peer_id: &PeerId,
+        connection_id: &ConnectionId,
+        _: &ConnectedPoint,
+        _: Self::ConnectionHandler,
+        _: usize,
+    ) {
+        if let Some(connections) = self.connections.get_mut(peer_id) {
+            connections.retain(|c| c != connection_id);
+            if connections.is_empty() {
+                self.connections.remove(peer_id);
+            }
+        }
+    }
+
+    fn inject_event(&mut self, peer_id: PeerId, _: ConnectionId, event: HandlerOut) {
+        match event {
+            HandlerOut::Received(chunk) => {
+                self.actions
+                    .push_back(NetworkBehaviourAction::GenerateEvent(
+                        ChunkStreamEvent::Received { peer_id, chunk },
+                    ));
+            }
+            HandlerOut::InboundFailed(e) => {
+                debug!(
+                    "(chunk_stream) inbound stream from {:?} failed with error {}",
+                    peer_id, e
+                );
+            }
+            HandlerOut::OutboundFailed { process_id, error } => {
+                debug!(
+                    "(chunk_stream) stream of process {} to {:?} failed with error {}",
+                    process_id, peer_id, error
+                );
+            }
+        }
+    }
+
+    fn poll(
+        &mut self,
+        _: &mut Context<'_>,
+        _: &mut impl PollParameters,
+    ) -> Poll<NetworkBehaviourAction<Self::OutEvent, Self::ConnectionHandler>> {
+        match self.actions.pop_front() {
+            Some(action) => Poll::Ready(action),
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HandlerIn {
+    Send(Chunk),
+    Close {
+        process_id: u32,
+    },
+    Expect {
+        process_id: u32,
+        chunk_size: Option<usize>,
+    },
+}
+
+#[derive(Debug)]
+pub enum HandlerOut {
+    Received(Chunk),
+    InboundFailed(String),
+    OutboundFailed { process_id: u32, error: String },
+}
+
+#[derive(Default)]
+pub struct ChunkStreamHandler {
+    /// Senders to outbound substreams keyed by process id
+    outbound: HashMap<u32, mpsc::Sender<Chunk>>,
+    /// Outbound substreams to be opened
+    pending_outbound: VecDeque<(u32, mpsc::Receiver<Chunk>)>,
+    writers: FuturesUnordered<BoxFuture<'static, (u32, io::Result<()>)>>,
+    readers: SelectAll<BoxStream<'static, io::Result<Chunk>>>,
+    /// Chunk sizes of processes whose inbound
+    /// substreams are accepted. Shared with readers.
+    expected: Arc<Mutex<HashMap<u32, usize>>>,
+}
+
+impl ConnectionHandler for ChunkStreamHandler {
+    type InEvent = HandlerIn;
+    type OutEvent = HandlerOut;
+    type Error = io::Error;
+    type InboundProtocol = ChunkStreamProtocol;
+    type OutboundProtocol = ChunkStreamProtocol;
+    type InboundOpenInfo = ();
+    type OutboundOpenInfo = (u32, mpsc::Receiver<Chunk>);
+
+    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol, Self::InboundOpenInfo> {
+        SubstreamProtocol::new(ChunkStreamProtocol, ())
+    }
+
+    fn inject_fully_negotiated_inbound(&mut self, substream: NegotiatedSubstream, _: ()) {
+        if self.readers.len() >= MAX_INBOUND_STREAMS {
+            debug!("(chunk_stream) dropped inbound stream over the limit");
+            return;
+        }
+        self.readers
+            .push(read_chunks(substream, self.expected.clone()));
+    }
+
+    fn inject_fully_negotiated_outbound(
+        &mut self,
+        substream: NegotiatedSubstream,
+        (process_id, receiver): Self::OutboundOpenInfo,
+    ) {
+        self.writers.push(
+            write_chunks(substream, process_id, receiver)
+                .map(move |res| (process_id, res))
+                .boxed(),
+        );
+    }
+
+    fn inject_event(&mut self, event: HandlerIn) {
+        match event {
+            HandlerIn::Send(chunk) => {
+                let process_id = chunk.process_id;
+                let chunk = match self.outbound.get_mut(&process_id) {
+                    Some(sender) => match sender.try_send(chunk) {
+                        Ok(()) => return,
+                        Err(e) if e.is_full() => {
+                            debug!(
+                                "(chunk_stream) dropped chunk of process {} over the buffer",
+                                process_id
+                            );
+                            return;
+                        }
+                        // substream failed, open a new one
+                        Err(e) => e.into_inner(),
+                    },
+                    None => chunk,
+                };
+
+                let (mut sender, receiver) = mpsc::channel(STREAM_BUFFER);
+                let _ = sender.try_send(chunk);
+                self.outbound.insert(process_id, sender);
+                self.pending_outbound.push_back((process_id, receiver));
+            }
+            HandlerIn::Close { process_id } => {
+                // substream is closed once the
+                // queued chunks are written
+                self.outbound.remove(&process_id);
+            }
+            HandlerIn::Expect {
+                process_id,
+                chunk_size,
+            } => {
+                let mut expected = self.expected.lock().unwrap();
+                match chunk_size {
+                    Some(chunk_size) => expected.insert(process_id, chunk_size),
+                    None => expected.remove(&process_id),
+                };
+            }
+        }
+    }
+
+    fn inject_dial_upgrade_error(
+        &mut self,
+        (process_id, _): Self::OutboundOpenInfo,
+        error: ConnectionHandlerUpgrErr<io::Error>,
+    ) {
+        self.outbound.remove(&process_id);
+        debug!(
+            "(chunk_stream) failed to open stream of process {} with error {}",
+            process_id, error
+        );
+    }
+
+    fn connection_keep_alive(&self) -> KeepAlive {
+        if self.outbound.is_empty()
+            && self.pending_outbound.is_empty()
+            && self.writers.is_empty()
+            && self.readers.is_empty()
+        {
+            KeepAlive::No
+        } else {
+            KeepAlive::Yes
+        }
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<
+        ConnectionHandlerEvent<
+            Self::OutboundProtocol,
+            Self::OutboundOpenInfo,
+            Self::OutEvent,
+            Self::Error,
+        >,
+    > {
+        if let Some(info) = self.pending_outbound.pop_front() {
+            return Poll::Ready(ConnectionHandlerEvent::OutboundSubstreamRequest {
+                protocol: SubstreamProtocol::new(ChunkStreamProtocol, info),
+            });
+        }
+
+        while let Poll::Ready(Some((process_id, res))) = self.writers.poll_next_unpin(cx) {
+            if self
+                .outbound
+                .get(&process_id)
+                .is_some_and(|s| s.is_closed())
+            {
+                self.outbound.remove(&process_id);
+            }
+            if let Err(e) = res {
+                return Poll::Ready(ConnectionHandlerEvent::Custom(HandlerOut::OutboundFailed {
+                    process_id,
+                    error: e.to_string(),
+                }));
+            }
+        }
+
+        match self.readers.poll_next_unpin(cx) {
+            Poll::Ready(Some(Ok(chunk))) => {
+                Poll::Ready(ConnectionHandlerEvent::Custom(HandlerOut::Received(chunk)))
+            }
+            Poll::Ready(Some(Err(e))) => Poll::Ready(ConnectionHandlerEvent::Custom(
+                HandlerOut::InboundFailed(e.to_string()),
+            )),
+            _ => Poll::Pending,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ChunkStreamProtocol;
+
+impl UpgradeInfo for ChunkStreamProtocol {
+    type Info = &'static [u8];
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once(CHUNK_STREAM_PROTOCOL_ID)
+    }
+}
+
+impl InboundUpgrade<NegotiatedSubstream> for ChunkStreamProtocol {
+    type Output = NegotiatedSubstream;
+    type Error = io::Error;
+    type Future = future::Ready<Result<Self::Output, Self::Error>>;
+
+    fn upgrade_inbound(self, substream: NegotiatedSubstream, _: Self::Info) -> Self::Future {
+        future::ok(substream)
+    }
+}
+
+impl OutboundUpgrade<NegotiatedSubstream> for ChunkStreamProtocol {
+    type Output = NegotiatedSubstream;
+    type Error = io::Error;
+    type Future = future::Ready<Result<Self::Output, Self::Error>>;
+
+    fn upgrade_outbound(self, substream: NegotiatedSubstream, _: Self::Info) -> Self::Future {
+        future::ok(substream)
+    }
+}
+
+/// Writes chunks received on `receiver` to `substream`
+/// till all senders are dropped
+async fn write_chunks(
+    mut substream: NegotiatedSubstream,
+    process_id: u32,
+    mut receiver: mpsc::Receiver<Chunk>,
+) -> io::Result<()> {
+    substream.write_all(&process_id.to_be_bytes()).await?;
+    while let Some(chunk) = receiver.next().await {
+        substream
+            .write_all(&(chunk.sequence_no as u64).to_be_bytes())
+            .await?;
+        write_length_prefixed(&mut substream, chunk.proof.concat()).await?;
+        write_length_prefixed(&mut substream, &chunk.data).await?;
+    }
+    substream.close().await
+}
+
+/// Reads chunks from `substream` till it is closed. Fails
+/// if chunks of the process of substream aren't `expected`.
+fn read_chunks(
+    substream: NegotiatedSubstream,
+    expected: Arc<Mutex<HashMap<u32, usize>>>,
+) -> BoxStream<'static, io::Result<Chunk>> {
+    stream::unfold(Some((substream, None)), move |state| {
+        let expected = expected.clone();
+        async move {
+            let (mut substream, process) = state?;
+
+            let (process_id, chunk_size) = match process {
+                Some(process) => process,
+                None => {
+                    let mut buf = [0u8; 4];
+                    if let Err(e) = substream.read_exact(&mut buf).await {
+                        return Some((Err(e), None));
+                    }
+                    let process_id = u32::from_be_bytes(buf);
+                    let chunk_size = expected.lock().unwrap().get(&process_id).copied();
+                    match chunk_size {
+                        Some(chunk_size) => (process_id, chunk_size),
+                        None => {
+                            let e = io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!("Chunks of process {} aren't expected", process_id),
+                            );
+                            return Some((Err(e), None));
+                        }
+                    }
+                }
+            };
+
+            let mut buf = [0u8; 8];
+            match substream.read_exact(&mut buf).await {
+                Ok(()) => {}
+                // substream closed
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return None,
+                Err(e) => return Some((Err(e), None)),
+            }
+            let proof = match read_proof(&mut substream).await {
+                Ok(proof) => proof,
+                Err(e) => return Some((Err(e), None)),
+            };
+            match read_length_prefixed(&mut substream, chunk_size).await {
+                Ok(data) => Some((
+                    Ok(Chunk {
+                        process_id,
+                        sequence_no: u64::from_be_bytes(buf) as usize,
+                        data,
+                        proof,
+                    }),
+                    Some((substream, Some((process_id, chunk_size)))),
+                )),
+                Err(e) => Some((Err(e), None)),
+            }
+        }
+    })
+    .boxed()
+}
+
+/// Reads merkle proof of a chunk from `substream`
+async fn read_proof(substream: &mut NegotiatedSubstream) -> io::Result<Vec<[u8; 32]>> {
+    let bytes = read_length_prefixed(substream, MAX_PROOF_LEN * 32).await?;
+    if bytes.len() % 32 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Merkle proof should consist of 32 byte hashes",
+        ));
+    }
+    Ok(bytes
+        .chunks(32)
+        .map(|hash| hash.try_into().expect("hash is 32 bytes"))
+        .collect())
+}
@@ -1,54 +1,264 @@
-use super::request_response::RequestResponse;
-use super::wallet::ReceiptWithSignatures;
-use ethers::types::{Address, U256};
+use super::request_response::{Format, ProtocolFormat, RequestResponse};
+use super::wallet::{Attestation, ReceiptWithSignatures};
+use ethers::types::{H256, U256};
 use libp2p::core::ProtocolName;
 use serde::{Deserialize, Serialize};
-use std::vec::Vec;
+use std::ops::Range;
 
 pub const FILE_EXCHANGE_PROTOCOL_ID: &[u8] = b"/dse/file-exchange/0.1";
+pub const FILE_EXCHANGE_BINCODE_PROTOCOL_ID: &[u8] = b"/dse/file-exchange/0.1/bincode";
+pub const FILE_EXCHANGE_CBOR_PROTOCOL_ID: &[u8] = b"/dse/file-exchange/0.1/cbor";
 
+/// File exchange protocol with messages serialized
+/// in the given format. JSON is the default.
 #[derive(Clone)]
-pub struct FileExchangeProtocol;
+pub struct FileExchangeProtocol(pub Format);
 
 impl ProtocolName for FileExchangeProtocol {
     fn protocol_name(&self) -> &[u8] {
-        FILE_EXCHANGE_PROTOCOL_ID
+        match self.0 {
+            Format::Json => FILE_EXCHANGE_PROTOCOL_ID,
+            Format::Bincode => FILE_EXCHANGE_BINCODE_PROTOCOL_ID,
+            Format::Cbor => FILE_EXCHANGE_CBOR_PROTOCOL_ID,
+        }
     }
 }
 
+impl ProtocolFormat for FileExchangeProtocol {
+    fn format(&self) -> Format {
+        self.0
+    }
+}
+
+/// Schedule on which requester pays for chunks
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum PaymentSchedule {
+    /// RFP after every `n` chunks sent
+    EveryChunks(usize),
+    /// RFP after every `m` bytes sent
+    EveryBytes(usize),
+    /// Requester pays for the next `window`
+    /// chunks before they are sent
+    Prepaid { window: usize },
+}
+
+impl PaymentSchedule {
+    /// Whether chunk at `sequence_no` can be sent, given chunks
+    /// from `rfp_sequence_no` onwards haven't been paid for.
+    /// `chunk_len` returns length of chunk at an index.
+    pub fn can_send(
+        &self,
+        sequence_no: usize,
+        rfp_sequence_no: usize,
+        chunk_len: impl Fn(usize) -> usize,
+    ) -> bool {
+        match self {
+            PaymentSchedule::EveryChunks(n) => {
+                sequence_no.saturating_sub(rfp_sequence_no) < (*n).max(1)
+            }
+            PaymentSchedule::EveryBytes(m) => {
+                (rfp_sequence_no..sequence_no).map(chunk_len).sum::<usize>() < *m
+            }
+            PaymentSchedule::Prepaid { .. } => sequence_no < rfp_sequence_no,
+        }
+    }
+
+    /// Returns index of chunk (exclusive) till which an
+    /// RFP is due, if any.
+    pub fn rfp_due(
+        &self,
+        sequence_no: usize,
+        rfp_sequence_no: usize,
+        end_sequence_no: usize,
+        chunk_len: impl Fn(usize) -> usize,
+    ) -> Option<usize> {
+        match self {
+            PaymentSchedule::EveryChunks(_) | PaymentSchedule::EveryBytes(_) => {
+                let unpaid = sequence_no > rfp_sequence_no;
+                if unpaid
+                    && (sequence_no >= end_sequence_no
+                        || !self.can_send(sequence_no, rfp_sequence_no, chunk_len))
+                {
+                    Some(sequence_no)
+                } else {
+                    None
+                }
+            }
+            PaymentSchedule::Prepaid { window } => {
+                if sequence_no >= rfp_sequence_no && rfp_sequence_no < end_sequence_no {
+                    Some((rfp_sequence_no + (*window).max(1)).min(end_sequence_no))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Whether requester should pay for chunks till
+    /// `to` (exclusive), given it has received chunks
+    /// till `sequence_no` and paid till `rfp_sequence_no`.
+    pub fn is_valid_rfp(
+        &self,
+        sequence_no: usize,
+        rfp_sequence_no: usize,
+        end_sequence_no: usize,
+        to: usize,
+    ) -> bool {
+        if to <= rfp_sequence_no || to > end_sequence_no {
+            return false;
+        }
+        match self {
+            PaymentSchedule::EveryChunks(_) | PaymentSchedule::EveryBytes(_) => to <= sequence_no,
+            PaymentSchedule::Prepaid { window } => to <= rfp_sequence_no + (*window).max(1),
+        }
+    }
+}
+
+/// Terms seeder is willing to seed a file on
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct QuoteTerms {
+    pub chunk_price: U256,
+    pub chunk_size: usize,
+    pub payment_schedule: PaymentSchedule,
+    /// Unix timestamp (in secs) till
+    /// which the quote is valid
+    pub valid_until: u64,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub enum FileExchangeRequest {
-    /// Requester wants the file
+    /// Requester wants the file, and asks
+    /// seeder for a quote
     ///
     /// TODO: Probably add merkle proof here
-    IWant { self_address: Address },
+    IWant {
+        /// Binds requester's peer id to its address
+        attestation: Attestation,
+        root_hash: H256,
+        /// Range of chunks wanted. `None`
+        /// for the whole file.
+        range: Option<Range<usize>>,
+    },
+    /// Requester's counter offer of price per chunk.
+    /// Seeder responds with an updated `Quote`, if it
+    /// doesn't reject it.
+    CounterOffer { quote_id: u32, chunk_price: U256 },
+    /// Requester accepts the quote
+    Accept { quote_id: u32 },
+    /// Seeder starts the process for accepted quote.
+    /// Process id is same as the quote id.
     IWillSeed {
         process_id: u32,
-        self_address: Address,
-        // chunk_price: U256
+        /// Binds seeder's peer id to its address
+        attestation: Attestation,
+        root_hash: H256,
     },
-    DataChunk {
+    /// Seeder requests payment. Requester responds
+    /// with the receipt it signed.
+    Rfp {
         process_id: u32,
-        sequence_no: usize,
+        /// Index of chunk (exclusive)
+        /// RFP pays till
         rfp_sequence_no: usize,
-        chunks: Vec<u8>,
+        receipt: ReceiptWithSignatures,
     },
-    Rfp {
+    /// Requester acknowledges that all chunks
+    /// before `sequence_no` have been received
+    ChunkAck { process_id: u32, sequence_no: usize },
+    /// Requester updates the chunk (exclusive) at which
+    /// process ends, to fetch rest from other seeders
+    UpdateRange {
         process_id: u32,
-        receipt: ReceiptWithSignatures,
+        end_sequence_no: usize,
     },
-    RfpC {
+    /// Requester resumes process after restart
+    Resume {
         process_id: u32,
-        receipt: ReceiptWithSignatures,
+        /// Index of the next chunk requester expects
+        sequence_no: usize,
+        /// Latest receipt signed by requester
+        receipt: Option<ReceiptWithSignatures>,
     },
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+impl FileExchangeRequest {
+    /// Whether request is sent by seeder to requester.
+    /// Rest are sent by requester to seeder. Each
+    /// request is only answered by the role it is for.
+    pub fn is_for_requester(&self) -> bool {
+        matches!(
+            self,
+            FileExchangeRequest::IWillSeed { .. } | FileExchangeRequest::Rfp { .. }
+        )
+    }
+
+    /// Whether request counts against rate limit of the
+    /// sender. Acks and requests carrying payments aren't
+    /// limited, since dropping them would stall transfers
+    /// or be taken as refusal to pay.
+    pub fn is_rate_limited(&self) -> bool {
+        matches!(
+            self,
+            FileExchangeRequest::IWant { .. }
+                | FileExchangeRequest::CounterOffer { .. }
+                | FileExchangeRequest::Accept { .. }
+                | FileExchangeRequest::UpdateRange { .. }
+        )
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub enum FileExchangeResponse {
     /// Acknowledges the request
     Ack,
-    /// Bad request
+    /// Request isn't handled by the receiver
     Bad,
+    /// Request was refused
+    Rejected { reason: String },
+    /// Request was dropped since sender exceeded
+    /// its rate limit. It can be sent again later.
+    RateLimited,
+    /// Seeder's quote for `range` of chunks of the
+    /// file, in response to `IWant` or `CounterOffer`
+    Quote {
+        quote_id: u32,
+        root_hash: H256,
+        range: Range<usize>,
+        /// Size of file in bytes
+        size: usize,
+        terms: QuoteTerms,
+    },
+    /// Receipt signed by requester, in response to `Rfp`
+    Receipt { receipt: ReceiptWithSignatures },
+}
+
+impl FileExchangeResponse {
+    /// Rejection with `error` as the reason
+    pub fn rejected(error: &anyhow::Error) -> Self {
+        FileExchangeResponse::Rejected {
+            reason: error.to_string(),
+        }
+    }
+
+    /// Fails if response isn't `Ack`
+    pub fn into_ack(self) -> anyhow::Result<()> {
+        match self {
+            FileExchangeResponse::Ack => Ok(()),
+            other => Err(other.into_error()),
+        }
+    }
+
+    /// Error for an unexpected response
+    pub fn into_error(self) -> anyhow::Error {
+        match self {
+            FileExchangeResponse::Rejected { reason } => {
+                anyhow::anyhow!("Request rejected: {}", reason)
+            }
+            FileExchangeResponse::Bad => anyhow::anyhow!("Request not handled by peer"),
+            FileExchangeResponse::RateLimited => anyhow::anyhow!("Request rate limited"),
+            other => anyhow::anyhow!("Unexpected response {:?}", other),
+        }
+    }
 }
 
 pub type FileExchangeCodec =
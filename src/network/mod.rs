@@ -1,33 +1,61 @@
+pub mod ban_list;
+pub mod chunk_stream;
 pub mod file_exchange;
-mod request_response;
+pub mod peer_table;
+pub mod rate_limit;
+pub mod request_response;
 
+use self::ban_list::BanList;
+use self::chunk_stream::{Chunk, ChunkStream, ChunkStreamEvent};
 use self::file_exchange::{
     FileExchangeCodec, FileExchangeProtocol, FileExchangeRequest, FileExchangeResponse,
 };
+use self::peer_table::PeerTable;
+use self::rate_limit::{PeerBuckets, RateLimit};
+use self::request_response::{CodecError, Format, SizeLimits};
 use super::wallet;
 use async_std::prelude::StreamExt;
-use ethers::types::Address;
+use ethers::types::H256;
 use libp2p::{
+    autonat::{self, NatStatus},
     core::{
         muxing::StreamMuxerBox,
-        transport::{upgrade::Version, Boxed},
+        transport::{upgrade::Version, Boxed, OptionalTransport},
         upgrade::SelectUpgrade,
     },
+    dcutr,
     dns::TokioDnsConfig,
+    identify::{Identify, IdentifyConfig, IdentifyEvent},
     identity::Keypair,
+    kad::{
+        record::{store::MemoryStore, Key},
+        GetProvidersError, GetProvidersOk, Kademlia, KademliaConfig, KademliaEvent, QueryId,
+        QueryResult,
+    },
+    mdns::{Mdns, MdnsConfig, MdnsEvent},
     mplex::MplexConfig,
+    multiaddr::Protocol,
     noise,
+    ping::{self, PingConfig, PingEvent, PingSuccess},
+    relay::v2::{
+        client::{transport::ClientTransport, Client, Event as ClientEvent},
+        relay::{Event as RelayEvent, Relay},
+    },
     request_response::{
-        ProtocolSupport, RequestId, RequestResponse, RequestResponseEvent, RequestResponseMessage,
-        ResponseChannel,
+        InboundFailure, OutboundFailure, ProtocolSupport, RequestId, RequestResponse,
+        RequestResponseEvent, RequestResponseMessage, ResponseChannel,
     },
-    swarm::{ConnectionHandlerUpgrErr, SwarmBuilder, SwarmEvent},
+    swarm::{behaviour::toggle::Toggle, AddressScore, ConnectionLimits, SwarmBuilder, SwarmEvent},
     tcp::TokioTcpConfig,
+    websocket::WsConfig,
     yamux::YamuxConfig,
     Multiaddr, NetworkBehaviour, PeerId, Swarm, Transport,
 };
 use log::{debug, error};
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
 use tokio::{
     io, select,
     sync::{broadcast, mpsc, oneshot},
@@ -37,24 +65,117 @@ use tokio::{
 #[behaviour(out_event = "BehaviourEvent")]
 struct Behaviour {
     file_exchange: RequestResponse<FileExchangeCodec>,
+    chunk_stream: ChunkStream,
+    kademlia: Kademlia<MemoryStore>,
+    /// Discovers peers on the local network.
+    /// Disabled for public nodes.
+    mdns: Toggle<Mdns>,
+    identify: Identify,
+    ping: ping::Behaviour,
+    /// Relays connections of peers behind NAT
+    relay: Toggle<Relay>,
+    /// Reserves slots on relays and
+    /// dials peers through them.
+    relay_client: Toggle<Client>,
+    /// Upgrades relayed connections to
+    /// direct ones through hole punching
+    dcutr: Toggle<dcutr::behaviour::Behaviour>,
+    /// Detects whether node is reachable
+    /// from outside, i.e. not behind NAT
+    autonat: autonat::Behaviour,
 }
 
 impl Behaviour {
-    pub async fn new(keypair: &Keypair) -> Result<Self, anyhow::Error> {
+    pub async fn new(
+        keypair: &Keypair,
+        config: &NetworkConfig,
+        relay_client: Option<Client>,
+    ) -> Result<Self, anyhow::Error> {
         let peer_id = keypair.public().to_peer_id();
 
+        let codec = config.file_exchange_format_limits.iter().fold(
+            FileExchangeCodec::new(config.file_exchange_limits),
+            |codec, (format, limits)| {
+                codec.with_protocol_limits(&FileExchangeProtocol(*format), *limits)
+            },
+        );
         let file_exchange = RequestResponse::new(
-            FileExchangeCodec::default(),
-            std::iter::once((FileExchangeProtocol, ProtocolSupport::Full)),
+            codec,
+            config
+                .file_exchange_formats
+                .iter()
+                .map(|format| (FileExchangeProtocol(*format), ProtocolSupport::Full)),
             Default::default(),
         );
 
-        Ok(Behaviour { file_exchange })
+        let mut kademlia_config = KademliaConfig::default();
+        kademlia_config
+            .set_protocol_name(DHT_PROTOCOL_ID)
+            .set_query_timeout(DHT_QUERY_TIMEOUT);
+        let mut kademlia =
+            Kademlia::with_config(peer_id, MemoryStore::new(peer_id), kademlia_config);
+        for (peer_id, address) in &config.bootstrap_peers {
+            kademlia.add_address(peer_id, address.clone());
+        }
+        if !config.bootstrap_peers.is_empty() {
+            kademlia.bootstrap()?;
+        }
+
+        let mdns = if config.enable_mdns {
+            Some(Mdns::new(MdnsConfig::default()).await?)
+        } else {
+            None
+        };
+
+        let identify = Identify::new(
+            IdentifyConfig::new(IDENTIFY_PROTOCOL_VERSION.into(), keypair.public())
+                .with_agent_version(format!("dse-client/{}", env!("CARGO_PKG_VERSION"))),
+        );
+
+        let relay = if config.enable_relay_server {
+            Some(Relay::new(peer_id, Default::default()))
+        } else {
+            None
+        };
+
+        let dcutr = if config.enable_hole_punching {
+            Some(dcutr::behaviour::Behaviour::new())
+        } else {
+            None
+        };
+
+        let mut autonat = autonat::Behaviour::new(peer_id, Default::default());
+        for (peer_id, address) in config.bootstrap_peers.iter().chain(&config.relays) {
+            autonat.add_server(*peer_id, Some(address.clone()));
+        }
+
+        Ok(Behaviour {
+            file_exchange,
+            chunk_stream: ChunkStream::default(),
+            kademlia,
+            mdns: mdns.into(),
+            identify,
+            ping: ping::Behaviour::new(PingConfig::new()),
+            relay: relay.into(),
+            relay_client: relay_client.into(),
+            dcutr: dcutr.into(),
+            autonat,
+        })
     }
 }
 
+#[allow(clippy::large_enum_variant)]
 pub enum BehaviourEvent {
     FileExchange(RequestResponseEvent<FileExchangeRequest, FileExchangeResponse>),
+    ChunkStream(ChunkStreamEvent),
+    Kademlia(KademliaEvent),
+    Mdns(MdnsEvent),
+    Identify(IdentifyEvent),
+    Ping(PingEvent),
+    Relay(RelayEvent),
+    RelayClient(ClientEvent),
+    Dcutr(dcutr::behaviour::Event),
+    Autonat(autonat::Event),
 }
 
 impl From<RequestResponseEvent<FileExchangeRequest, FileExchangeResponse>> for BehaviourEvent {
@@ -63,6 +184,68 @@ impl From<RequestResponseEvent<FileExchangeRequest, FileExchangeResponse>> for B
     }
 }
 
+impl From<KademliaEvent> for BehaviourEvent {
+    fn from(event: KademliaEvent) -> Self {
+        BehaviourEvent::Kademlia(event)
+    }
+}
+
+impl From<IdentifyEvent> for BehaviourEvent {
+    fn from(event: IdentifyEvent) -> Self {
+        BehaviourEvent::Identify(event)
+    }
+}
+
+impl From<PingEvent> for BehaviourEvent {
+    fn from(event: PingEvent) -> Self {
+        BehaviourEvent::Ping(event)
+    }
+}
+
+impl From<MdnsEvent> for BehaviourEvent {
+    fn from(event: MdnsEvent) -> Self {
+        BehaviourEvent::Mdns(event)
+    }
+}
+
+impl From<RelayEvent> for BehaviourEvent {
+    fn from(event: RelayEvent) -> Self {
+        BehaviourEvent::Relay(event)
+    }
+}
+
+impl From<ClientEvent> for BehaviourEvent {
+    fn from(event: ClientEvent) -> Self {
+        BehaviourEvent::RelayClient(event)
+    }
+}
+
+impl From<dcutr::behaviour::Event> for BehaviourEvent {
+    fn from(event: dcutr::behaviour::Event) -> Self {
+        BehaviourEvent::Dcutr(event)
+    }
+}
+
+impl From<autonat::Event> for BehaviourEvent {
+    fn from(event: autonat::Event) -> Self {
+        BehaviourEvent::Autonat(event)
+    }
+}
+
+impl From<ChunkStreamEvent> for BehaviourEvent {
+    fn from(event: ChunkStreamEvent) -> Self {
+        BehaviourEvent::ChunkStream(event)
+    }
+}
+
+/// Protocol name of the DHT. Distinct from the
+/// default one, so that only dse nodes join it.
+pub const DHT_PROTOCOL_ID: &[u8] = b"/dse/kad/0.1";
+/// Protocol family announced over identify
+pub const IDENTIFY_PROTOCOL_VERSION: &str = "/dse/0.1";
+/// Time after which a DHT query is stopped
+const DHT_QUERY_TIMEOUT: Duration = Duration::from_secs(30);
+
 pub struct CustomExecutor;
 impl libp2p::core::Executor for CustomExecutor {
     fn exec(
@@ -73,15 +256,49 @@ impl libp2p::core::Executor for CustomExecutor {
     }
 }
 
-/// Uses TCP encrypted using noise DH and MPlex for multiplexing
-pub fn build_transport(identity_keypair: &Keypair) -> io::Result<Boxed<(PeerId, StreamMuxerBox)>> {
+/// Transports enabled in `config`, i.e. TCP and/or websocket,
+/// plus relayed connections through `relay_transport` if given.
+/// Connections are encrypted using noise DH and multiplexed
+/// using Yamux or MPlex.
+pub fn build_transport(
+    identity_keypair: &Keypair,
+    config: &TransportConfig,
+    relay_transport: Option<ClientTransport>,
+) -> io::Result<Boxed<(PeerId, StreamMuxerBox)>> {
+    if !config.tcp && !config.websocket {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "No transport enabled",
+        ));
+    }
+
     // noise config
     let keypair = noise::Keypair::<noise::X25519>::new()
         .into_authentic(identity_keypair)
         .unwrap();
     let noise_config = noise::NoiseConfig::xx(keypair).into_authenticated();
 
-    Ok(TokioDnsConfig::system(TokioTcpConfig::new())?
+    // relay only takes `/p2p-circuit` addresses and websocket
+    // only takes `/ws` addresses, rest fall through to TCP
+    let relay = match relay_transport {
+        Some(relay_transport) => OptionalTransport::some(relay_transport),
+        None => OptionalTransport::none(),
+    };
+    let tcp = || TokioDnsConfig::system(TokioTcpConfig::new());
+    let websocket = if config.websocket {
+        OptionalTransport::some(WsConfig::new(tcp()?))
+    } else {
+        OptionalTransport::none()
+    };
+    let tcp = if config.tcp {
+        OptionalTransport::some(tcp()?)
+    } else {
+        OptionalTransport::none()
+    };
+
+    Ok(relay
+        .or_transport(websocket)
+        .or_transport(tcp)
         .upgrade(Version::V1)
         .authenticate(noise_config)
         .multiplex(SelectUpgrade::new(
@@ -94,6 +311,105 @@ pub fn build_transport(identity_keypair: &Keypair) -> io::Result<Boxed<(PeerId,
         .boxed())
 }
 
+/// Transports the node listens and dials on
+#[derive(Debug, Clone)]
+pub struct TransportConfig {
+    pub tcp: bool,
+    /// Websocket over TCP, i.e. `/ws` addresses. Lets
+    /// peers behind HTTP(S) only proxies connect.
+    pub websocket: bool,
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        Self {
+            tcp: true,
+            websocket: false,
+        }
+    }
+}
+
+/// Configuration of the network
+#[derive(Debug, Clone)]
+pub struct NetworkConfig {
+    pub transports: TransportConfig,
+    /// Addresses listened on besides the default one,
+    /// e.g. a `/ws` address along with a TCP one
+    pub listen_addresses: Vec<Multiaddr>,
+    /// Formats supported for file exchange
+    /// messages, in order of preference
+    pub file_exchange_formats: Vec<Format>,
+    /// Size limits of file exchange messages
+    pub file_exchange_limits: SizeLimits,
+    /// Size limits of file exchange messages
+    /// overridden per format
+    pub file_exchange_format_limits: HashMap<Format, SizeLimits>,
+    /// Peers used to join the DHT
+    pub bootstrap_peers: Vec<(PeerId, Multiaddr)>,
+    /// Addresses other peers can reach the node on,
+    /// announced in provider records. If empty,
+    /// listen addresses are announced.
+    pub external_addresses: Vec<Multiaddr>,
+    /// Whether peers on the local network are
+    /// discovered over mDNS. Should be disabled
+    /// for public nodes.
+    pub enable_mdns: bool,
+    /// Cap on established connections. `None` for no cap.
+    pub max_connections: Option<u32>,
+    /// Cap on established connections per peer
+    pub max_connections_per_peer: Option<u32>,
+    /// Rate of file exchange requests accepted from a peer.
+    /// Rest are rejected by the network. Chunk acks
+    /// aren't limited.
+    pub inbound_request_limit: RateLimit,
+    /// Whether node relays connections for peers behind
+    /// NAT. Should only be enabled for public nodes. Relayed
+    /// circuits are limited in duration and bytes.
+    pub enable_relay_server: bool,
+    /// Whether node can reserve slots on
+    /// relays and dial peers through them
+    pub enable_relay_client: bool,
+    /// Whether relayed connections are upgraded to
+    /// direct ones through hole punching (DCUtR)
+    pub enable_hole_punching: bool,
+    /// Relays node listens on, so that peers can
+    /// reach it when it is behind NAT
+    pub relays: Vec<(PeerId, Multiaddr)>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            transports: TransportConfig::default(),
+            listen_addresses: Vec::new(),
+            // JSON keeps control and payment
+            // messages debuggable
+            file_exchange_formats: vec![Format::Json, Format::Cbor, Format::Bincode],
+            // control and payment messages are small
+            file_exchange_limits: SizeLimits {
+                request: 64 * 1024,
+                response: 64 * 1024,
+            },
+            file_exchange_format_limits: HashMap::new(),
+            bootstrap_peers: Vec::new(),
+            external_addresses: Vec::new(),
+            enable_mdns: true,
+            max_connections: Some(256),
+            // requests sent while dialing might
+            // each open a connection
+            max_connections_per_peer: Some(8),
+            inbound_request_limit: RateLimit {
+                burst: 64,
+                per_sec: 16,
+            },
+            enable_relay_server: false,
+            enable_relay_client: true,
+            enable_hole_punching: true,
+            relays: Vec::new(),
+        }
+    }
+}
+
 pub struct Network {
     /// keypair of the node
     pub keypair: Keypair,
@@ -101,6 +417,14 @@ pub struct Network {
     pub node_address: Option<Multiaddr>,
 
     swarm: Swarm<Behaviour>,
+    /// Whether listen addresses are announced
+    /// as external addresses
+    announce_listen_addresses: bool,
+    peer_table: PeerTable,
+    /// Peers connections are refused with
+    ban_list: BanList,
+    /// Tokens for inbound file exchange requests of peers
+    inbound_request_buckets: PeerBuckets,
 
     command_sender: mpsc::Sender<Command>,
     command_receiver: mpsc::Receiver<Command>,
@@ -111,17 +435,65 @@ pub struct Network {
         HashMap<(PeerId, RequestId), oneshot::Sender<Result<FileExchangeResponse, anyhow::Error>>>,
     pending_exchange_inbound_response:
         HashMap<(PeerId, RequestId), oneshot::Sender<Result<(), anyhow::Error>>>,
-    // exchange_inbound_response_channels:
-    //     HashMap<(PeerId, RequestId), ResponseChannel<FileExchangeResponse>>,
+    pending_get_providers: HashMap<QueryId, oneshot::Sender<anyhow::Result<HashSet<PeerId>>>>,
+    /// Channels of inbound requests the
+    /// application hasn't responded to yet
+    exchange_inbound_response_channels:
+        HashMap<(PeerId, RequestId), ResponseChannel<FileExchangeResponse>>,
+    /// Last codec error that closed connection with peer.
+    /// Attached to request failures caused by the closure.
+    codec_errors: HashMap<PeerId, CodecError>,
 }
 
 impl Network {
-    pub async fn new(keypair: Keypair, listen_on: Multiaddr) -> Result<Self, anyhow::Error> {
+    pub async fn new(
+        keypair: Keypair,
+        listen_on: Multiaddr,
+        config: NetworkConfig,
+    ) -> Result<Self, anyhow::Error> {
+        let (relay_transport, relay_client) = if config.enable_relay_client {
+            let (transport, client) =
+                Client::new_transport_and_behaviour(keypair.public().to_peer_id());
+            (Some(transport), Some(client))
+        } else {
+            (None, None)
+        };
+        let transport = build_transport(&keypair, &config.transports, relay_transport)?;
+        Self::build(keypair, transport, relay_client, listen_on, config).await
+    }
+
+    /// Creates network on an injected `transport`, e.g. an
+    /// in-memory one. Relay client is disabled, since its
+    /// transport has to be combined with the injected one.
+    pub async fn with_transport(
+        keypair: Keypair,
+        transport: Boxed<(PeerId, StreamMuxerBox)>,
+        listen_on: Multiaddr,
+        config: NetworkConfig,
+    ) -> Result<Self, anyhow::Error> {
+        Self::build(keypair, transport, None, listen_on, config).await
+    }
+
+    async fn build(
+        keypair: Keypair,
+        transport: Boxed<(PeerId, StreamMuxerBox)>,
+        relay_client: Option<Client>,
+        listen_on: Multiaddr,
+        config: NetworkConfig,
+    ) -> Result<Self, anyhow::Error> {
+        if config.file_exchange_formats.is_empty() {
+            return Err(anyhow::anyhow!("No file exchange format configured"));
+        }
+
         // Build swarm
-        let transport = build_transport(&keypair)?;
-        let behaviour = Behaviour::new(&keypair).await?;
+        let behaviour = Behaviour::new(&keypair, &config, relay_client).await?;
         let mut swarm = SwarmBuilder::new(transport, behaviour, keypair.public().to_peer_id())
             .executor(Box::new(CustomExecutor))
+            .connection_limits(
+                ConnectionLimits::default()
+                    .with_max_established(config.max_connections)
+                    .with_max_established_per_peer(config.max_connections_per_peer),
+            )
             .build();
 
         // Start listening on default addr
@@ -131,14 +503,40 @@ impl Network {
                 e
             );
         }
+        // listening through relay reserves a slot on it
+        let relay_addresses = config.relays.iter().map(|(peer_id, address)| {
+            address
+                .clone()
+                .with(Protocol::P2p((*peer_id).into()))
+                .with(Protocol::P2pCircuit)
+        });
+        for address in config
+            .listen_addresses
+            .iter()
+            .cloned()
+            .chain(relay_addresses)
+        {
+            if let Err(e) = swarm.listen_on(address.clone()) {
+                error!("Failed to start listening on {} with error {}", address, e);
+            }
+        }
+        for address in &config.external_addresses {
+            swarm.add_external_address(address.clone(), AddressScore::Infinite);
+        }
 
         let (command_sender, command_receiver) = mpsc::channel(10);
-        let (network_event_sender, network_event_receiver) = broadcast::channel(20);
+        // chunks are emitted as events, thus capacity
+        // should be enough for windows of all processes
+        let (network_event_sender, network_event_receiver) = broadcast::channel(1024);
 
         Ok(Self {
             keypair,
             node_address: None,
             swarm,
+            announce_listen_addresses: config.external_addresses.is_empty(),
+            peer_table: PeerTable::default(),
+            ban_list: BanList::default(),
+            inbound_request_buckets: PeerBuckets::new(config.inbound_request_limit),
 
             command_sender,
             command_receiver,
@@ -147,7 +545,9 @@ impl Network {
 
             pending_exchange_outbound_requests: Default::default(),
             pending_exchange_inbound_response: Default::default(),
-            // exchange_inbound_response_channels: Default::default(),
+            pending_get_providers: Default::default(),
+            exchange_inbound_response_channels: Default::default(),
+            codec_errors: Default::default(),
         })
     }
 
@@ -182,6 +582,14 @@ impl Network {
         self.command_sender.clone()
     }
 
+    pub fn peer_table(&self) -> PeerTable {
+        self.peer_table.clone()
+    }
+
+    pub fn ban_list(&self) -> BanList {
+        self.ban_list.clone()
+    }
+
     async fn command_handler(&mut self, command: Command) {
         match command {
             Command::SendFileRequest {
@@ -197,14 +605,277 @@ impl Network {
                 self.pending_exchange_outbound_requests
                     .insert((peer_id, request_id), sender);
             }
+            Command::SendFileResponse {
+                peer_id,
+                request_id,
+                response,
+                sender,
+            } => {
+                let channel = match self
+                    .exchange_inbound_response_channels
+                    .remove(&(peer_id, request_id))
+                {
+                    Some(channel) => channel,
+                    None => {
+                        let _ = sender.send(Err(anyhow::anyhow!(
+                            "Response channel missing for request id {}",
+                            request_id
+                        )));
+                        return;
+                    }
+                };
+                match self
+                    .swarm
+                    .behaviour_mut()
+                    .file_exchange
+                    .send_response(channel, response)
+                {
+                    Ok(_) => {
+                        self.pending_exchange_inbound_response
+                            .insert((peer_id, request_id), sender);
+                    }
+                    Err(_) => {
+                        let _ = sender.send(Err(anyhow::anyhow!(
+                            "Connection for request id {} closed",
+                            request_id
+                        )));
+                    }
+                }
+            }
+            Command::Dial {
+                peer_id,
+                address,
+                sender,
+            } => {
+                if let Some(address) = address {
+                    self.swarm
+                        .behaviour_mut()
+                        .file_exchange
+                        .add_address(&peer_id, address.clone());
+                    self.swarm
+                        .behaviour_mut()
+                        .kademlia
+                        .add_address(&peer_id, address);
+                }
+                if self.ban_list.is_banned(&peer_id) {
+                    let _ = sender.send(Err(anyhow::anyhow!("Peer {} is banned", peer_id)));
+                    return;
+                }
+                if self.swarm.is_connected(&peer_id) {
+                    let _ = sender.send(Ok(()));
+                    return;
+                }
+                let _ = sender.send(self.swarm.dial(peer_id).map_err(|e| e.into()));
+            }
+            Command::SendChunk { peer_id, chunk } => {
+                if let Err(e) = self
+                    .swarm
+                    .behaviour_mut()
+                    .chunk_stream
+                    .send_chunk(&peer_id, chunk)
+                {
+                    debug!("(chunk_stream) dropped chunk with error {}", e);
+                }
+            }
+            Command::ExpectChunks {
+                peer_id,
+                process_id,
+                chunk_size,
+            } => {
+                self.swarm
+                    .behaviour_mut()
+                    .chunk_stream
+                    .expect_chunks(&peer_id, process_id, chunk_size);
+            }
+            Command::CloseChunkStream {
+                peer_id,
+                process_id,
+            } => {
+                self.swarm
+                    .behaviour_mut()
+                    .chunk_stream
+                    .close_stream(&peer_id, process_id);
+            }
+            Command::StartProviding { root_hash, sender } => {
+                let res = self
+                    .swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .start_providing(Key::new(&root_hash));
+                let _ = sender.send(res.map(|_| ()).map_err(|e| e.into()));
+            }
+            Command::StopProviding { root_hash } => {
+                self.swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .stop_providing(&Key::new(&root_hash));
+            }
+            Command::GetProviders { root_hash, sender } => {
+                let query_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .get_providers(Key::new(&root_hash));
+                self.pending_get_providers.insert(query_id, sender);
+            }
+        }
+    }
+
+    /// Codec error that caused the failure, if
+    /// failure is due to closed connection
+    fn failure_codec_error(&self, peer_id: &PeerId, closed: bool) -> Option<CodecError> {
+        if closed {
+            self.codec_errors.get(peer_id).cloned()
+        } else {
+            None
         }
     }
 
-    async fn swarm_event_handler(
+    async fn swarm_event_handler<E: std::error::Error + 'static>(
         &mut self,
-        event: SwarmEvent<BehaviourEvent, ConnectionHandlerUpgrErr<std::io::Error>>,
+        event: SwarmEvent<BehaviourEvent, E>,
     ) {
         match event {
+            SwarmEvent::Behaviour(BehaviourEvent::ChunkStream(ChunkStreamEvent::Received {
+                peer_id,
+                chunk,
+            })) => {
+                emit_event(
+                    &self.network_event_sender,
+                    NetworkEvent::ChunkReceived {
+                        sender_peer_id: peer_id,
+                        chunk,
+                    },
+                )
+                .await;
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Kademlia(
+                KademliaEvent::OutboundQueryCompleted { id, result, .. },
+            )) => match result {
+                QueryResult::GetProviders(res) => {
+                    let providers = match res {
+                        Ok(GetProvidersOk { providers, .. }) => Ok(providers),
+                        // providers found before timing out are still useful
+                        Err(GetProvidersError::Timeout { providers, .. })
+                            if !providers.is_empty() =>
+                        {
+                            Ok(providers)
+                        }
+                        Err(e) => Err(e.into()),
+                    };
+                    if let Some(sender) = self.pending_get_providers.remove(&id) {
+                        let local_peer_id = *self.swarm.local_peer_id();
+                        let _ = sender.send(providers.map(|mut providers| {
+                            providers.remove(&local_peer_id);
+                            providers
+                        }));
+                    }
+                }
+                QueryResult::StartProviding(Err(e)) | QueryResult::RepublishProvider(Err(e)) => {
+                    debug!(
+                        "(kademlia) failed to publish provider record with error {}",
+                        e
+                    );
+                }
+                QueryResult::Bootstrap(Err(e)) => {
+                    debug!("(kademlia) bootstrap failed with error {}", e);
+                }
+                _ => {}
+            },
+            SwarmEvent::Behaviour(BehaviourEvent::Identify(IdentifyEvent::Received {
+                peer_id,
+                info,
+            })) => {
+                debug!(
+                    "(identify) {:?} is {} listening on {:?}",
+                    peer_id, info.agent_version, info.listen_addrs
+                );
+                // only peers that are part of the DHT
+                // are added to the routing table
+                if info
+                    .protocols
+                    .iter()
+                    .any(|p| p.as_bytes() == DHT_PROTOCOL_ID)
+                {
+                    for address in &info.listen_addrs {
+                        self.swarm
+                            .behaviour_mut()
+                            .kademlia
+                            .add_address(&peer_id, address.clone());
+                    }
+                }
+                self.peer_table.update(peer_id, |p| {
+                    p.agent_version = Some(info.agent_version);
+                    p.protocol_version = Some(info.protocol_version);
+                    p.protocols = info.protocols;
+                    p.listen_addrs = info.listen_addrs;
+                });
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Ping(PingEvent { peer, result })) => {
+                match result {
+                    Ok(PingSuccess::Ping { rtt }) => {
+                        self.peer_table.update(peer, |p| p.rtt = Some(rtt));
+                    }
+                    Ok(PingSuccess::Pong) => {}
+                    // connection is closed by ping
+                    Err(e) => debug!("(ping) {:?} failed with error {}", peer, e),
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Autonat(autonat::Event::StatusChanged {
+                old,
+                new,
+            })) => {
+                debug!("(autonat) status changed from {:?} to {:?}", old, new);
+                emit_event(
+                    &self.network_event_sender,
+                    NetworkEvent::NatStatusChanged { status: new },
+                )
+                .await;
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::RelayClient(event)) => match event {
+                ClientEvent::ReservationReqAccepted {
+                    relay_peer_id,
+                    renewal,
+                    ..
+                } => {
+                    debug!(
+                        "(relay) reservation on {:?} accepted, renewal {}",
+                        relay_peer_id, renewal
+                    );
+                }
+                ClientEvent::ReservationReqFailed {
+                    relay_peer_id,
+                    error,
+                    ..
+                } => {
+                    error!(
+                        "(relay) reservation on {:?} failed with error {:?}",
+                        relay_peer_id, error
+                    );
+                }
+                event => debug!("(relay) {:?}", event),
+            },
+            SwarmEvent::Behaviour(BehaviourEvent::Dcutr(event)) => {
+                debug!("(dcutr) {:?}", event);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Relay(event)) => {
+                debug!("(relay) {:?}", event);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Mdns(MdnsEvent::Discovered(peers))) => {
+                for (peer_id, address) in peers {
+                    debug!("(mdns) discovered {:?} on {:?}", peer_id, address);
+                    let behaviour = self.swarm.behaviour_mut();
+                    behaviour
+                        .file_exchange
+                        .add_address(&peer_id, address.clone());
+                    behaviour.kademlia.add_address(&peer_id, address.clone());
+                    emit_event(
+                        &self.network_event_sender,
+                        NetworkEvent::PeerDiscovered { peer_id, address },
+                    )
+                    .await;
+                }
+            }
             SwarmEvent::Behaviour(BehaviourEvent::FileExchange(event)) => match event {
                 RequestResponseEvent::Message { peer, message } => match message {
                     RequestResponseMessage::Request {
@@ -212,12 +883,34 @@ impl Network {
                         request,
                         channel,
                     } => {
-                        // Send response immediately
-                        let _ = self
-                            .swarm
-                            .behaviour_mut()
-                            .file_exchange
-                            .send_response(channel, FileExchangeResponse::Ack);
+                        // peer might have been banned while connected
+                        if self.ban_list.is_banned(&peer) {
+                            debug!("(swarm) disconnecting banned peer {:?}", peer);
+                            let _ = self.swarm.disconnect_peer_id(peer);
+                            return;
+                        }
+
+                        if request.is_rate_limited()
+                            && !self
+                                .inbound_request_buckets
+                                .try_acquire(peer, Instant::now())
+                        {
+                            debug!("(swarm) rate limited request from {:?}", peer);
+                            let _ = self
+                                .swarm
+                                .behaviour_mut()
+                                .file_exchange
+                                .send_response(channel, FileExchangeResponse::RateLimited);
+                            return;
+                        }
+
+                        // application responds through `SendFileResponse`.
+                        // Channels of requests nobody answered are closed
+                        // once they time out.
+                        self.exchange_inbound_response_channels
+                            .retain(|_, channel| channel.is_open());
+                        self.exchange_inbound_response_channels
+                            .insert((peer, request_id), channel);
 
                         emit_event(
                             &self.network_event_sender,
@@ -237,7 +930,7 @@ impl Network {
                             .pending_exchange_outbound_requests
                             .remove(&(peer, request_id))
                         {
-                            sender.send(Ok(response));
+                            let _ = sender.send(Ok(response));
                         } else {
                             error!(
                                 "(file_exchange) response channel missing for request id {} for peer {}",
@@ -252,7 +945,7 @@ impl Network {
                         .pending_exchange_inbound_response
                         .remove(&(peer, request_id))
                     {
-                        sender.send(Ok(()));
+                        let _ = sender.send(Ok(()));
                     } else {
                         error!(
                             "(file_exchange) response channel missing for request id {}",
@@ -265,34 +958,83 @@ impl Network {
                     request_id,
                     error,
                 } => {
+                    let codec_error =
+                        self.failure_codec_error(&peer, error == OutboundFailure::ConnectionClosed);
+                    match &codec_error {
+                        Some(e) => error!(
+                            "(file_exchange) request {} to {:?} failed with error {}",
+                            request_id, peer, e
+                        ),
+                        None => debug!(
+                            "(file_exchange) request {} to {:?} failed with error {}",
+                            request_id, peer, error
+                        ),
+                    }
                     if let Some(sender) = self
                         .pending_exchange_outbound_requests
                         .remove(&(peer, request_id))
                     {
-                        sender.send(Err(error.into()));
+                        let _ = sender.send(Err(match &codec_error {
+                            Some(e) => anyhow::Error::new(e.clone()).context(error.clone()),
+                            None => error.clone().into(),
+                        }));
                     } else {
                         error!(
                             "(file_exchange) response channel missing for request id {}",
                             request_id
                         );
                     }
+
+                    emit_event(
+                        &self.network_event_sender,
+                        NetworkEvent::FileExchangeOutboundFailure {
+                            peer_id: peer,
+                            request_id,
+                            error,
+                            codec_error,
+                        },
+                    )
+                    .await;
                 }
                 RequestResponseEvent::InboundFailure {
                     peer,
                     request_id,
                     error,
                 } => {
+                    self.exchange_inbound_response_channels
+                        .remove(&(peer, request_id));
+                    let codec_error =
+                        self.failure_codec_error(&peer, error == InboundFailure::ConnectionClosed);
+                    match &codec_error {
+                        Some(e) => error!(
+                            "(file_exchange) request {} from {:?} failed with error {}",
+                            request_id, peer, e
+                        ),
+                        None => debug!(
+                            "(file_exchange) request {} from {:?} failed with error {}",
+                            request_id, peer, error
+                        ),
+                    }
                     if let Some(sender) = self
                         .pending_exchange_inbound_response
                         .remove(&(peer, request_id))
                     {
-                        sender.send(Err(error.into()));
-                    } else {
-                        error!(
-                            "(commit) response channel missing for request id {}",
-                            request_id
-                        );
+                        let _ = sender.send(Err(match &codec_error {
+                            Some(e) => anyhow::Error::new(e.clone()).context(error.clone()),
+                            None => error.clone().into(),
+                        }));
                     }
+
+                    emit_event(
+                        &self.network_event_sender,
+                        NetworkEvent::FileExchangeInboundFailure {
+                            peer_id: peer,
+                            request_id,
+                            error,
+                            codec_error,
+                        },
+                    )
+                    .await;
                 }
                 _ => {}
             },
@@ -316,6 +1058,26 @@ impl Network {
                     "(swarm) connection established {:?} {:?} {:?} ",
                     peer_id, endpoint, num_established
                 );
+                if self.ban_list.is_banned(&peer_id) {
+                    debug!("(swarm) disconnecting banned peer {:?}", peer_id);
+                    let _ = self.swarm.disconnect_peer_id(peer_id);
+                    return;
+                }
+                self.codec_errors.remove(&peer_id);
+                self.peer_table.update(peer_id, |p| {
+                    p.connected = true;
+                    if !endpoint.is_relayed() {
+                        p.direct_connections += 1;
+                    }
+                });
+                self.inbound_request_buckets.connected(&peer_id);
+                if num_established.get() == 1 {
+                    emit_event(
+                        &self.network_event_sender,
+                        NetworkEvent::PeerConnected { peer_id },
+                    )
+                    .await;
+                }
             }
             SwarmEvent::ConnectionClosed {
                 peer_id,
@@ -327,6 +1089,37 @@ impl Network {
                     "(swarm) connection closed {:?} {:?} {:?} {:?} ",
                     peer_id, endpoint, num_established, cause
                 );
+                if !endpoint.is_relayed() {
+                    self.peer_table.update(peer_id, |p| {
+                        p.direct_connections = p.direct_connections.saturating_sub(1)
+                    });
+                }
+                if num_established == 0 {
+                    self.peer_table.update(peer_id, |p| p.connected = false);
+                    self.inbound_request_buckets
+                        .disconnected(peer_id, Instant::now());
+                    self.exchange_inbound_response_channels
+                        .retain(|(p, _), _| *p != peer_id);
+                }
+
+                // Codec errors close the connection. Request
+                // failures caused by it are emitted afterwards.
+                if let Some(codec_error) = cause.as_ref().and_then(|e| CodecError::find(e)) {
+                    let codec_error = codec_error.clone();
+                    error!(
+                        "(file_exchange) connection with {:?} closed with error {}",
+                        peer_id, codec_error
+                    );
+                    self.codec_errors.insert(peer_id, codec_error.clone());
+                    emit_event(
+                        &self.network_event_sender,
+                        NetworkEvent::CodecError {
+                            peer_id,
+                            error: codec_error,
+                        },
+                    )
+                    .await;
+                }
             }
             SwarmEvent::NewListenAddr {
                 listener_id,
@@ -336,6 +1129,10 @@ impl Network {
                     "(swarm) new listener id {:?} and addr {:?} ",
                     listener_id, address
                 );
+                if self.announce_listen_addresses {
+                    self.swarm
+                        .add_external_address(address.clone(), AddressScore::Finite(1));
+                }
                 self.node_address = Some(address);
                 // self.network_event_sender.send(NetworkEvent::NewListenAddr { listener_id, address}).await.expect("Network evvent message dropped");
             }
@@ -350,6 +1147,182 @@ async fn emit_event(sender: &broadcast::Sender<NetworkEvent>, event: NetworkEven
     }
 }
 
+/// Sends `request` to `peer_id` over file exchange
+/// protocol and waits for the response
+pub async fn send_file_request(
+    command_sender: &mpsc::Sender<Command>,
+    peer_id: PeerId,
+    request: FileExchangeRequest,
+) -> anyhow::Result<FileExchangeResponse> {
+    let (sender, receiver) = oneshot::channel();
+    command_sender
+        .send(Command::SendFileRequest {
+            peer_id,
+            request,
+            sender,
+        })
+        .await?;
+    receiver.await?
+}
+
+/// Sends `request` to `peer_id` over file exchange protocol
+/// without waiting for the response. Requests are handed over
+/// to the network in order. Failures are only logged.
+pub async fn send_file_request_detached(
+    command_sender: &mpsc::Sender<Command>,
+    peer_id: PeerId,
+    request: FileExchangeRequest,
+) -> anyhow::Result<()> {
+    let (sender, receiver) = oneshot::channel();
+    command_sender
+        .send(Command::SendFileRequest {
+            peer_id,
+            request,
+            sender,
+        })
+        .await?;
+    tokio::spawn(async move {
+        if let Ok(Err(e)) = receiver.await {
+            debug!(
+                "(file_exchange) request to {:?} failed with error {}",
+                peer_id, e
+            );
+        }
+    });
+    Ok(())
+}
+
+/// Responds to request `request_id` received from `peer_id`
+/// without waiting for it to be sent. Failures are only logged.
+pub async fn send_file_response(
+    command_sender: &mpsc::Sender<Command>,
+    peer_id: PeerId,
+    request_id: RequestId,
+    response: FileExchangeResponse,
+) -> anyhow::Result<()> {
+    let (sender, receiver) = oneshot::channel();
+    command_sender
+        .send(Command::SendFileResponse {
+            peer_id,
+            request_id,
+            response,
+            sender,
+        })
+        .await?;
+    tokio::spawn(async move {
+        if let Ok(Err(e)) = receiver.await {
+            debug!(
+                "(file_exchange) response to {:?} for request id {} failed with error {}",
+                peer_id, request_id, e
+            );
+        }
+    });
+    Ok(())
+}
+
+/// Announces on the DHT that this node provides file
+/// with `root_hash`. Record is published in background.
+pub async fn start_providing(
+    command_sender: &mpsc::Sender<Command>,
+    root_hash: H256,
+) -> anyhow::Result<()> {
+    let (sender, receiver) = oneshot::channel();
+    command_sender
+        .send(Command::StartProviding { root_hash, sender })
+        .await?;
+    receiver.await?
+}
+
+/// Stops announcing that this node provides file with
+/// `root_hash`. Records published earlier expire on their own.
+pub async fn stop_providing(
+    command_sender: &mpsc::Sender<Command>,
+    root_hash: H256,
+) -> anyhow::Result<()> {
+    command_sender
+        .send(Command::StopProviding { root_hash })
+        .await?;
+    Ok(())
+}
+
+/// Looks up peers providing file with `root_hash` on the DHT
+pub async fn get_providers(
+    command_sender: &mpsc::Sender<Command>,
+    root_hash: H256,
+) -> anyhow::Result<HashSet<PeerId>> {
+    let (sender, receiver) = oneshot::channel();
+    command_sender
+        .send(Command::GetProviders { root_hash, sender })
+        .await?;
+    receiver.await?
+}
+
+/// Sends `chunk` to `peer_id` over chunk stream
+/// protocol. Chunks aren't acknowledged by the network.
+pub async fn send_chunk(
+    command_sender: &mpsc::Sender<Command>,
+    peer_id: PeerId,
+    chunk: Chunk,
+) -> anyhow::Result<()> {
+    command_sender
+        .send(Command::SendChunk { peer_id, chunk })
+        .await?;
+    Ok(())
+}
+
+/// Accepts chunks of process `process_id` from `peer_id` of up
+/// to `chunk_size` bytes, or stops accepting them if `None`.
+/// Chunks of processes that aren't expected are dropped.
+pub async fn expect_chunks(
+    command_sender: &mpsc::Sender<Command>,
+    peer_id: PeerId,
+    process_id: u32,
+    chunk_size: Option<usize>,
+) -> anyhow::Result<()> {
+    command_sender
+        .send(Command::ExpectChunks {
+            peer_id,
+            process_id,
+            chunk_size,
+        })
+        .await?;
+    Ok(())
+}
+
+/// Closes chunk stream of process `process_id` with `peer_id`
+pub async fn close_chunk_stream(
+    command_sender: &mpsc::Sender<Command>,
+    peer_id: PeerId,
+    process_id: u32,
+) -> anyhow::Result<()> {
+    command_sender
+        .send(Command::CloseChunkStream {
+            peer_id,
+            process_id,
+        })
+        .await?;
+    Ok(())
+}
+
+/// Dials `peer_id`. If `address` isn't provided, addresses
+/// known to the swarm are used. Succeeds if already connected.
+pub async fn dial(
+    command_sender: &mpsc::Sender<Command>,
+    peer_id: PeerId,
+    address: Option<Multiaddr>,
+) -> anyhow::Result<()> {
+    let (sender, receiver) = oneshot::channel();
+    command_sender
+        .send(Command::Dial {
+            peer_id,
+            address,
+            sender,
+        })
+        .await?;
+    receiver.await?
+}
+
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 pub enum Command {
     SendFileRequest {
@@ -357,8 +1330,44 @@ pub enum Command {
         request: FileExchangeRequest,
         sender: oneshot::Sender<anyhow::Result<FileExchangeResponse>>,
     },
+    SendFileResponse {
+        peer_id: PeerId,
+        request_id: RequestId,
+        response: FileExchangeResponse,
+        sender: oneshot::Sender<anyhow::Result<()>>,
+    },
+    Dial {
+        peer_id: PeerId,
+        address: Option<Multiaddr>,
+        sender: oneshot::Sender<anyhow::Result<()>>,
+    },
+    SendChunk {
+        peer_id: PeerId,
+        chunk: Chunk,
+    },
+    ExpectChunks {
+        peer_id: PeerId,
+        process_id: u32,
+        chunk_size: Option<usize>,
+    },
+    CloseChunkStream {
+        peer_id: PeerId,
+        process_id: u32,
+    },
+    StartProviding {
+        root_hash: H256,
+        sender: oneshot::Sender<anyhow::Result<()>>,
+    },
+    StopProviding {
+        root_hash: H256,
+    },
+    GetProviders {
+        root_hash: H256,
+        sender: oneshot::Sender<anyhow::Result<HashSet<PeerId>>>,
+    },
 }
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone)]
 pub enum NetworkEvent {
     FileExchangeRequest {
@@ -366,4 +1375,497 @@ pub enum NetworkEvent {
         request_id: RequestId,
         request: FileExchangeRequest,
     },
+    ChunkReceived {
+        sender_peer_id: PeerId,
+        chunk: Chunk,
+    },
+    /// Request sent to peer failed. `codec_error` is
+    /// set if failure was caused by a codec error.
+    FileExchangeOutboundFailure {
+        peer_id: PeerId,
+        request_id: RequestId,
+        error: OutboundFailure,
+        codec_error: Option<CodecError>,
+    },
+    /// Request received from peer failed. `codec_error`
+    /// is set if failure was caused by a codec error.
+    FileExchangeInboundFailure {
+        peer_id: PeerId,
+        request_id: RequestId,
+        error: InboundFailure,
+        codec_error: Option<CodecError>,
+    },
+    /// Message exchanged with peer couldn't be encoded
+    /// or decoded, thus connection was closed
+    CodecError { peer_id: PeerId, error: CodecError },
+    /// First connection with peer was established
+    PeerConnected { peer_id: PeerId },
+    /// Peer found on the local network
+    PeerDiscovered { peer_id: PeerId, address: Multiaddr },
+    /// Reachability of node from outside changed
+    NatStatusChanged { status: NatStatus },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ban_list::Ban;
+    use super::file_exchange::FILE_EXCHANGE_PROTOCOL_ID;
+    use super::request_response::{CodecErrorKind, MessageType};
+    use super::*;
+
+    struct TestNode {
+        peer_id: PeerId,
+        address: Multiaddr,
+        command_sender: mpsc::Sender<Command>,
+        peer_table: PeerTable,
+        ban_list: BanList,
+        network_event_receiver: broadcast::Receiver<NetworkEvent>,
+    }
+
+    /// Address on loopback with a free port
+    fn local_address() -> Multiaddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap()
+    }
+
+    async fn spawn_node(bootstrap_peers: Vec<(PeerId, Multiaddr)>) -> TestNode {
+        spawn_node_with_config(NetworkConfig {
+            bootstrap_peers,
+            ..Default::default()
+        })
+        .await
+    }
+
+    async fn spawn_node_with_config(config: NetworkConfig) -> TestNode {
+        spawn_node_on(local_address(), config).await
+    }
+
+    async fn spawn_node_on(address: Multiaddr, config: NetworkConfig) -> TestNode {
+        let keypair = Keypair::generate_ed25519();
+        let peer_id = keypair.public().to_peer_id();
+        let config = NetworkConfig {
+            enable_mdns: false,
+            ..config
+        };
+        let network = Network::new(keypair, address.clone(), config)
+            .await
+            .unwrap();
+        let node = TestNode {
+            peer_id,
+            address,
+            command_sender: network.network_command_sender(),
+            peer_table: network.peer_table(),
+            ban_list: network.ban_list(),
+            network_event_receiver: network.network_event_receiver(),
+        };
+        tokio::spawn(network.run());
+        node
+    }
+
+    #[tokio::test]
+    async fn providers_are_found_through_dht() {
+        let boot = spawn_node(vec![]).await;
+        let seeder = spawn_node(vec![(boot.peer_id, boot.address.clone())]).await;
+        let requester = spawn_node(vec![(boot.peer_id, boot.address.clone())]).await;
+
+        let root_hash = H256::random();
+        start_providing(&seeder.command_sender, root_hash)
+            .await
+            .unwrap();
+
+        // record is published in background
+        let mut providers = HashSet::new();
+        for _ in 0..50 {
+            providers = get_providers(&requester.command_sender, root_hash)
+                .await
+                .unwrap_or_default();
+            if !providers.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(providers, HashSet::from([seeder.peer_id]));
+
+        // provider is reachable by its peer id alone
+        dial(&requester.command_sender, seeder.peer_id, None)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn no_providers_for_unknown_file() {
+        let boot = spawn_node(vec![]).await;
+        let requester = spawn_node(vec![(boot.peer_id, boot.address.clone())]).await;
+
+        let providers = get_providers(&requester.command_sender, H256::random())
+            .await
+            .unwrap_or_default();
+        assert!(providers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn peers_are_identified_and_pinged() {
+        let seeder = spawn_node(vec![]).await;
+        let requester = spawn_node(vec![]).await;
+        dial(
+            &requester.command_sender,
+            seeder.peer_id,
+            Some(seeder.address.clone()),
+        )
+        .await
+        .unwrap();
+
+        let mut info = None;
+        for _ in 0..50 {
+            info = requester
+                .peer_table
+                .get(&seeder.peer_id)
+                .filter(|p| p.agent_version.is_some() && p.rtt.is_some());
+            if info.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let info = info.expect("seeder not identified");
+        assert!(info.connected);
+        assert!(info
+            .agent_version
+            .as_ref()
+            .is_some_and(|v| v.starts_with("dse-client/")));
+        assert!(info.supports(FILE_EXCHANGE_PROTOCOL_ID));
+        assert!(info.supports(DHT_PROTOCOL_ID));
+        assert!(info.listen_addrs.contains(&seeder.address));
+    }
+
+    #[tokio::test]
+    async fn connections_with_banned_peers_are_refused() {
+        let seeder = spawn_node(vec![]).await;
+        let requester = spawn_node(vec![]).await;
+        seeder.ban_list.ban(requester.peer_id, Ban::Permanent);
+
+        assert!(dial(
+            &seeder.command_sender,
+            requester.peer_id,
+            Some(requester.address.clone()),
+        )
+        .await
+        .is_err());
+
+        // seeder closes connection dialed by requester
+        let _ = dial(
+            &requester.command_sender,
+            seeder.peer_id,
+            Some(seeder.address.clone()),
+        )
+        .await;
+        let mut connected = true;
+        for _ in 0..50 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            connected = requester
+                .peer_table
+                .get(&seeder.peer_id)
+                .is_some_and(|p| p.connected);
+            if !connected {
+                break;
+            }
+        }
+        assert!(!connected);
+        assert!(!seeder.peer_table.connected().contains(&requester.peer_id));
+    }
+
+    #[tokio::test]
+    async fn inbound_requests_are_rate_limited() {
+        let seeder = spawn_node_with_config(NetworkConfig {
+            inbound_request_limit: RateLimit {
+                burst: 2,
+                per_sec: 0,
+            },
+            ..Default::default()
+        })
+        .await;
+        let requester = spawn_node(vec![]).await;
+        dial(
+            &requester.command_sender,
+            seeder.peer_id,
+            Some(seeder.address.clone()),
+        )
+        .await
+        .unwrap();
+
+        // application on seeder never responds, thus only
+        // the request over the limit gets a response
+        let requests = (0..3).map(|end_sequence_no| {
+            Box::pin(send_file_request(
+                &requester.command_sender,
+                seeder.peer_id,
+                FileExchangeRequest::UpdateRange {
+                    process_id: 0,
+                    end_sequence_no,
+                },
+            ))
+        });
+        let (response, ..) = tokio::time::timeout(
+            Duration::from_secs(10),
+            futures::future::select_all(requests),
+        )
+        .await
+        .expect("no request was rejected");
+        assert!(matches!(response, Ok(FileExchangeResponse::RateLimited)));
+
+        // acks and requests of payments aren't limited
+        for request in [
+            FileExchangeRequest::ChunkAck {
+                process_id: 0,
+                sequence_no: 0,
+            },
+            FileExchangeRequest::Resume {
+                process_id: 0,
+                sequence_no: 0,
+                receipt: None,
+            },
+        ] {
+            let response = send_file_request(&requester.command_sender, seeder.peer_id, request);
+            assert!(tokio::time::timeout(Duration::from_secs(1), response)
+                .await
+                .is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn only_expected_chunks_are_received() {
+        let mut requester = spawn_node(vec![]).await;
+        let seeder = spawn_node(vec![]).await;
+        dial(
+            &seeder.command_sender,
+            requester.peer_id,
+            Some(requester.address.clone()),
+        )
+        .await
+        .unwrap();
+        for process_id in [2, 3] {
+            expect_chunks(
+                &requester.command_sender,
+                seeder.peer_id,
+                process_id,
+                Some(4),
+            )
+            .await
+            .unwrap();
+        }
+        // connection is registered by chunk stream in background
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        // chunks of unexpected process and the ones
+        // over chunk size of the process are dropped
+        let chunks = [(1, 0, 4), (2, 0, 4), (2, 1, 4), (3, 0, 5)];
+        for (process_id, sequence_no, len) in chunks {
+            let chunk = Chunk {
+                process_id,
+                sequence_no,
+                data: vec![0; len],
+                proof: vec![],
+            };
+            send_chunk(&seeder.command_sender, requester.peer_id, chunk)
+                .await
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        let mut received = Vec::new();
+        while let Ok(Ok(event)) = tokio::time::timeout(
+            Duration::from_millis(500),
+            requester.network_event_receiver.recv(),
+        )
+        .await
+        {
+            if let NetworkEvent::ChunkReceived { chunk, .. } = event {
+                received.push((chunk.process_id, chunk.sequence_no));
+            }
+        }
+        assert_eq!(received, vec![(2, 0), (2, 1)]);
+    }
+
+    #[tokio::test]
+    async fn peers_connect_over_websocket() {
+        let transports = TransportConfig {
+            tcp: false,
+            websocket: true,
+        };
+        let seeder = spawn_node_on(
+            local_address().with(Protocol::Ws("/".into())),
+            NetworkConfig {
+                transports: transports.clone(),
+                ..Default::default()
+            },
+        )
+        .await;
+        let requester = spawn_node_on(
+            local_address().with(Protocol::Ws("/".into())),
+            NetworkConfig {
+                transports,
+                ..Default::default()
+            },
+        )
+        .await;
+
+        dial(
+            &requester.command_sender,
+            seeder.peer_id,
+            Some(seeder.address.clone()),
+        )
+        .await
+        .unwrap();
+        let mut rtt = None;
+        for _ in 0..50 {
+            rtt = requester.peer_table.rtt(&seeder.peer_id);
+            if rtt.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(rtt.is_some());
+    }
+
+    #[tokio::test]
+    async fn peers_behind_nat_are_reached_through_relay() {
+        let relay = spawn_node_with_config(NetworkConfig {
+            enable_relay_server: true,
+            ..Default::default()
+        })
+        .await;
+        let seeder = spawn_node_with_config(NetworkConfig {
+            relays: vec![(relay.peer_id, relay.address.clone())],
+            ..Default::default()
+        })
+        .await;
+        let requester = spawn_node(vec![]).await;
+
+        // seeder is only reachable through its reservation on relay
+        let circuit_address = relay
+            .address
+            .clone()
+            .with(Protocol::P2p(relay.peer_id.into()))
+            .with(Protocol::P2pCircuit);
+        let mut rtt = None;
+        for _ in 0..50 {
+            let _ = dial(
+                &requester.command_sender,
+                seeder.peer_id,
+                Some(circuit_address.clone()),
+            )
+            .await;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            rtt = requester.peer_table.rtt(&seeder.peer_id);
+            if rtt.is_some() {
+                break;
+            }
+        }
+        assert!(rtt.is_some());
+        assert!(requester
+            .peer_table
+            .get(&relay.peer_id)
+            .is_some_and(|p| p.connected));
+    }
+
+    #[tokio::test]
+    async fn relayed_connections_are_upgraded_to_direct_ones() {
+        let relay = spawn_node_with_config(NetworkConfig {
+            enable_relay_server: true,
+            ..Default::default()
+        })
+        .await;
+        let seeder = spawn_node_with_config(NetworkConfig {
+            relays: vec![(relay.peer_id, relay.address.clone())],
+            ..Default::default()
+        })
+        .await;
+        let requester = spawn_node(vec![]).await;
+
+        // requester first reaches seeder through relay. Seeder
+        // then dials requester on the addresses it announces.
+        let circuit_address = relay
+            .address
+            .clone()
+            .with(Protocol::P2p(relay.peer_id.into()))
+            .with(Protocol::P2pCircuit);
+        let direct = |node: &TestNode, peer_id| {
+            node.peer_table
+                .get(peer_id)
+                .is_some_and(|p| p.direct_connections > 0)
+        };
+        let mut upgraded = false;
+        for _ in 0..50 {
+            if !requester
+                .peer_table
+                .get(&seeder.peer_id)
+                .is_some_and(|p| p.connected)
+            {
+                let _ = dial(
+                    &requester.command_sender,
+                    seeder.peer_id,
+                    Some(circuit_address.clone()),
+                )
+                .await;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            upgraded = direct(&requester, &seeder.peer_id) && direct(&seeder, &requester.peer_id);
+            if upgraded {
+                break;
+            }
+        }
+        assert!(upgraded);
+    }
+
+    #[tokio::test]
+    async fn codec_errors_are_recovered_from_closed_connections() {
+        let mut seeder = spawn_node_with_config(NetworkConfig {
+            file_exchange_limits: SizeLimits {
+                request: 8,
+                response: 64 * 1024,
+            },
+            ..Default::default()
+        })
+        .await;
+        let requester = spawn_node(vec![]).await;
+        dial(
+            &requester.command_sender,
+            seeder.peer_id,
+            Some(seeder.address.clone()),
+        )
+        .await
+        .unwrap();
+
+        // request is within requester's limits, but not seeder's
+        let response = tokio::time::timeout(
+            Duration::from_secs(10),
+            send_file_request(
+                &requester.command_sender,
+                seeder.peer_id,
+                FileExchangeRequest::Accept { quote_id: 1 },
+            ),
+        )
+        .await
+        .expect("request did not fail");
+        assert!(response.is_err());
+
+        let error = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if let Ok(NetworkEvent::CodecError { peer_id, error }) =
+                    seeder.network_event_receiver.recv().await
+                {
+                    if peer_id == requester.peer_id {
+                        return error;
+                    }
+                }
+            }
+        })
+        .await
+        .expect("codec error not emitted");
+        assert_eq!(error.message_type, MessageType::Request);
+        assert!(matches!(
+            error.kind,
+            CodecErrorKind::TooLarge { max_size: 8, .. }
+        ));
+    }
 }
@@ -0,0 +1,63 @@
+use libp2p::{Multiaddr, PeerId};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Metadata of a peer learnt through
+/// identify and ping protocols
+#[derive(Debug, Clone, Default)]
+pub struct PeerInfo {
+    pub agent_version: Option<String>,
+    pub protocol_version: Option<String>,
+    /// Protocols supported by the peer
+    pub protocols: Vec<String>,
+    pub listen_addrs: Vec<Multiaddr>,
+    /// Latest round trip time
+    pub rtt: Option<Duration>,
+    pub connected: bool,
+    /// No. of connections that aren't relayed
+    pub direct_connections: usize,
+}
+
+impl PeerInfo {
+    /// Whether peer supports `protocol`
+    pub fn supports(&self, protocol: &[u8]) -> bool {
+        self.protocols.iter().any(|p| p.as_bytes() == protocol)
+    }
+}
+
+/// Peers known to the network. Shared
+/// with the application for selecting peers.
+#[derive(Clone, Default)]
+pub struct PeerTable {
+    peers: Arc<Mutex<HashMap<PeerId, PeerInfo>>>,
+}
+
+impl PeerTable {
+    pub fn get(&self, peer_id: &PeerId) -> Option<PeerInfo> {
+        self.peers.lock().unwrap().get(peer_id).cloned()
+    }
+
+    /// Latest round trip time to peer
+    pub fn rtt(&self, peer_id: &PeerId) -> Option<Duration> {
+        self.peers.lock().unwrap().get(peer_id).and_then(|p| p.rtt)
+    }
+
+    /// Connected peers
+    pub fn connected(&self) -> Vec<PeerId> {
+        self.peers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, p)| p.connected)
+            .map(|(peer_id, _)| *peer_id)
+            .collect()
+    }
+
+    /// Updates info of peer with `f`
+    pub(super) fn update(&self, peer_id: PeerId, f: impl FnOnce(&mut PeerInfo)) {
+        f(self.peers.lock().unwrap().entry(peer_id).or_default());
+    }
+}
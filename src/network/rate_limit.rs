@@ -0,0 +1,126 @@
+use libp2p::PeerId;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Time buckets of disconnected peers are kept for,
+/// so that peers can't refill them by reconnecting
+pub const BUCKET_RETENTION: Duration = Duration::from_secs(60);
+
+/// Rate of inbound requests allowed per peer
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    /// Requests allowed in a burst
+    pub burst: u32,
+    /// Requests allowed per sec on average
+    pub per_sec: u32,
+}
+
+/// Token bucket refilled at `per_sec` tokens
+/// per sec, holding at most `burst` tokens
+#[derive(Debug)]
+pub struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            last_refill: now,
+        }
+    }
+
+    /// Takes a token if available
+    pub fn try_acquire(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.limit.per_sec as f64)
+            .min(self.limit.burst as f64);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Token buckets of peers. Buckets are kept for
+/// `BUCKET_RETENTION` after peer disconnects.
+#[derive(Debug)]
+pub struct PeerBuckets {
+    limit: RateLimit,
+    buckets: HashMap<PeerId, TokenBucket>,
+    /// When peers whose buckets are kept disconnected
+    disconnected_at: HashMap<PeerId, Instant>,
+}
+
+impl PeerBuckets {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: HashMap::new(),
+            disconnected_at: HashMap::new(),
+        }
+    }
+
+    /// Takes a token of `peer_id` if available
+    pub fn try_acquire(&mut self, peer_id: PeerId, now: Instant) -> bool {
+        let limit = self.limit;
+        self.buckets
+            .entry(peer_id)
+            .or_insert_with(|| TokenBucket::new(limit, now))
+            .try_acquire(now)
+    }
+
+    pub fn connected(&mut self, peer_id: &PeerId) {
+        self.disconnected_at.remove(peer_id);
+    }
+
+    /// Drops buckets of peers that have been
+    /// disconnected for `BUCKET_RETENTION`
+    pub fn disconnected(&mut self, peer_id: PeerId, now: Instant) {
+        self.disconnected_at.insert(peer_id, now);
+        let buckets = &mut self.buckets;
+        self.disconnected_at.retain(|peer_id, at| {
+            let keep = now.saturating_duration_since(*at) < BUCKET_RETENTION;
+            if !keep {
+                buckets.remove(peer_id);
+            }
+            keep
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_outlive_disconnects() {
+        let limit = RateLimit {
+            burst: 1,
+            per_sec: 0,
+        };
+        let mut buckets = PeerBuckets::new(limit);
+        let (peer, other) = (PeerId::random(), PeerId::random());
+        let now = Instant::now();
+        assert!(buckets.try_acquire(peer, now));
+
+        // reconnecting does not refill the bucket
+        buckets.disconnected(peer, now);
+        buckets.connected(&peer);
+        assert!(!buckets.try_acquire(peer, now));
+
+        // bucket is dropped once retention passes
+        buckets.disconnected(peer, now);
+        buckets.disconnected(other, now + BUCKET_RETENTION);
+        assert!(buckets.try_acquire(peer, now + BUCKET_RETENTION));
+    }
+}
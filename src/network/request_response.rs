@@ -1,17 +1,196 @@
 use async_trait::async_trait;
-use futures::{AsyncRead, AsyncWrite};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite};
 use libp2p::{
     core::{
-        upgrade::{read_length_prefixed, write_length_prefixed},
+        upgrade::{read_varint, write_length_prefixed},
         ProtocolName,
     },
     request_response::RequestResponseCodec,
 };
 use serde::{de::DeserializeOwned, ser::Serialize};
-use std::{io, marker::PhantomData};
+use std::{collections::HashMap, fmt, io, marker::PhantomData};
+
+/// Default max size of a message in bytes
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1_000_000;
+
+/// Serialization format of messages
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Format {
+    Json,
+    Bincode,
+    Cbor,
+}
+
+impl Format {
+    pub fn serialize<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, CodecErrorKind> {
+        let res = match self {
+            Format::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+            Format::Bincode => bincode::serialize(value).map_err(|e| e.to_string()),
+            Format::Cbor => serde_cbor::to_vec(value).map_err(|e| e.to_string()),
+        };
+        res.map_err(|cause| CodecErrorKind::Serialize {
+            format: *self,
+            cause,
+        })
+    }
+
+    pub fn deserialize<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecErrorKind> {
+        let res = match self {
+            Format::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+            Format::Bincode => bincode::deserialize(bytes).map_err(|e| e.to_string()),
+            Format::Cbor => serde_cbor::from_slice(bytes).map_err(|e| e.to_string()),
+        };
+        res.map_err(|cause| CodecErrorKind::Deserialize {
+            format: *self,
+            cause,
+        })
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Json => write!(f, "json"),
+            Format::Bincode => write!(f, "bincode"),
+            Format::Cbor => write!(f, "cbor"),
+        }
+    }
+}
+
+/// Protocol whose messages are serialized in a
+/// particular format. Each format of a protocol
+/// should have a distinct protocol name, so that
+/// format is negotiated along with the protocol.
+pub trait ProtocolFormat {
+    fn format(&self) -> Format;
+}
+
+/// Max size in bytes of requests and responses
+#[derive(Clone, Copy, Debug)]
+pub struct SizeLimits {
+    pub request: usize,
+    pub response: usize,
+}
+
+impl Default for SizeLimits {
+    fn default() -> Self {
+        Self {
+            request: DEFAULT_MAX_MESSAGE_SIZE,
+            response: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+}
+
+/// Type of message exchanged over the protocol
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageType {
+    Request,
+    Response,
+}
+
+impl fmt::Display for MessageType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageType::Request => write!(f, "request"),
+            MessageType::Response => write!(f, "response"),
+        }
+    }
+}
+
+/// Cause of a codec failure
+#[derive(Clone, Debug)]
+pub enum CodecErrorKind {
+    Serialize {
+        format: Format,
+        cause: String,
+    },
+    Deserialize {
+        format: Format,
+        cause: String,
+    },
+    /// Message exceeds size limit of its protocol
+    TooLarge {
+        size: usize,
+        max_size: usize,
+    },
+}
+
+/// Failure to encode or decode a message of a protocol
+#[derive(Clone, Debug)]
+pub struct CodecError {
+    pub protocol: String,
+    pub message_type: MessageType,
+    pub kind: CodecErrorKind,
+}
+
+impl CodecError {
+    fn new<P: ProtocolName>(protocol: &P, message_type: MessageType, kind: CodecErrorKind) -> Self {
+        Self {
+            protocol: String::from_utf8_lossy(protocol.protocol_name()).into_owned(),
+            message_type,
+            kind,
+        }
+    }
+
+    /// Finds codec error within chain of `error`'s sources.
+    ///
+    /// Codec errors are returned to libp2p wrapped in
+    /// `io::Error`, which then close the connection.
+    pub fn find<'a>(error: &'a (dyn std::error::Error + 'static)) -> Option<&'a CodecError> {
+        let mut next = Some(error);
+        while let Some(e) = next {
+            if let Some(codec_error) = e.downcast_ref::<CodecError>() {
+                return Some(codec_error);
+            }
+            if let Some(codec_error) = e
+                .downcast_ref::<io::Error>()
+                .and_then(|e| e.get_ref())
+                .and_then(|e| e.downcast_ref::<CodecError>())
+            {
+                return Some(codec_error);
+            }
+            next = e.source();
+        }
+        None
+    }
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            CodecErrorKind::Serialize { format, cause } => write!(
+                f,
+                "failed to serialize {} {} as {}: {}",
+                self.protocol, self.message_type, format, cause
+            ),
+            CodecErrorKind::Deserialize { format, cause } => write!(
+                f,
+                "failed to deserialize {} {} as {}: {}",
+                self.protocol, self.message_type, format, cause
+            ),
+            CodecErrorKind::TooLarge { size, max_size } => write!(
+                f,
+                "{} {} of {} bytes exceeds limit of {} bytes",
+                self.protocol, self.message_type, size, max_size
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+impl From<CodecError> for io::Error {
+    fn from(e: CodecError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
 
 #[derive(Clone)]
 pub struct RequestResponse<P, REQ, RES> {
+    /// Limits of protocols without their own limits
+    default_limits: SizeLimits,
+    /// Limits keyed by protocol name
+    protocol_limits: HashMap<Vec<u8>, SizeLimits>,
     protocol: PhantomData<P>,
     request: PhantomData<REQ>,
     response: PhantomData<RES>,
@@ -19,18 +198,77 @@ pub struct RequestResponse<P, REQ, RES> {
 
 impl<P, REQ, RES> Default for RequestResponse<P, REQ, RES> {
     fn default() -> Self {
+        Self::new(SizeLimits::default())
+    }
+}
+
+impl<P, REQ, RES> RequestResponse<P, REQ, RES> {
+    pub fn new(default_limits: SizeLimits) -> Self {
         Self {
-            protocol: PhantomData::<P>::default(),
-            request: PhantomData::<REQ>::default(),
-            response: PhantomData::<RES>::default(),
+            default_limits,
+            protocol_limits: HashMap::new(),
+            protocol: PhantomData,
+            request: PhantomData,
+            response: PhantomData,
         }
     }
 }
 
+impl<P: ProtocolName, REQ, RES> RequestResponse<P, REQ, RES> {
+    /// Sets size limits of `protocol`
+    pub fn with_protocol_limits(mut self, protocol: &P, limits: SizeLimits) -> Self {
+        self.protocol_limits
+            .insert(protocol.protocol_name().to_vec(), limits);
+        self
+    }
+
+    /// Checks that message of `size` bytes is
+    /// within the limits of `protocol`
+    fn check_size(&self, protocol: &P, message_type: MessageType, size: usize) -> io::Result<()> {
+        let limits = self
+            .protocol_limits
+            .get(protocol.protocol_name())
+            .unwrap_or(&self.default_limits);
+        let max_size = match message_type {
+            MessageType::Request => limits.request,
+            MessageType::Response => limits.response,
+        };
+        if size <= max_size {
+            return Ok(());
+        }
+
+        Err(CodecError::new(
+            protocol,
+            message_type,
+            CodecErrorKind::TooLarge { size, max_size },
+        )
+        .into())
+    }
+
+    /// Reads length prefixed message, failing
+    /// early if it exceeds the size limit
+    async fn read_message<T>(
+        &self,
+        protocol: &P,
+        message_type: MessageType,
+        io: &mut T,
+    ) -> io::Result<Vec<u8>>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let size = read_varint(io).await?;
+        self.check_size(protocol, message_type, size)?;
+
+        let mut buf = vec![0; size];
+        io.read_exact(&mut buf).await?;
+        Ok(buf)
+    }
+}
+
 #[async_trait]
 impl<P, REQ, RES> RequestResponseCodec for RequestResponse<P, REQ, RES>
 where
-    P: ProtocolName + Sync + Send + Clone,
+    P: ProtocolName + ProtocolFormat + Sync + Send + Clone,
     REQ: Sized + Serialize + DeserializeOwned + Clone + Sync + Send,
     RES: Sized + Serialize + DeserializeOwned + Clone + Sync + Send,
 {
@@ -46,61 +284,225 @@ where
     where
         T: AsyncRead + Unpin + Send,
     {
-        let vec = read_length_prefixed(io, 1000000).await?;
-        match serde_json::from_slice(&vec) {
-            Ok(v) => Ok(v),
-            Err(e) => Err(io::ErrorKind::Other.into()),
-        }
+        let vec = self
+            .read_message(protocol, MessageType::Request, io)
+            .await?;
+        protocol
+            .format()
+            .deserialize(&vec)
+            .map_err(|kind| CodecError::new(protocol, MessageType::Request, kind).into())
     }
 
     async fn read_response<T>(
         &mut self,
-        _: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
     ) -> io::Result<Self::Response>
     where
         T: AsyncRead + Unpin + Send,
     {
-        let vec = read_length_prefixed(io, 1000000).await?;
-        match serde_json::from_slice(&vec) {
-            Ok(v) => Ok(v),
-            Err(_) => Err(io::ErrorKind::Other.into()),
-        }
+        let vec = self
+            .read_message(protocol, MessageType::Response, io)
+            .await?;
+        protocol
+            .format()
+            .deserialize(&vec)
+            .map_err(|kind| CodecError::new(protocol, MessageType::Response, kind).into())
     }
 
     async fn write_request<T>(
         &mut self,
-        _: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
         req: Self::Request,
     ) -> io::Result<()>
     where
         T: AsyncWrite + Unpin + Send,
     {
-        match serde_json::to_vec(&req) {
-            Ok(val) => {
-                write_length_prefixed(io, &val).await?;
-                Ok(())
-            }
-            Err(_) => Err(io::ErrorKind::Other.into()),
-        }
+        let val = protocol
+            .format()
+            .serialize(&req)
+            .map_err(|kind| CodecError::new(protocol, MessageType::Request, kind))?;
+        self.check_size(protocol, MessageType::Request, val.len())?;
+        write_length_prefixed(io, &val).await
     }
 
     async fn write_response<T>(
         &mut self,
-        _protocol: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
         res: Self::Response,
     ) -> io::Result<()>
     where
         T: AsyncWrite + Unpin + Send,
     {
-        match serde_json::to_vec(&res) {
-            Ok(val) => {
-                write_length_prefixed(io, &val).await?;
-                Ok(())
-            }
-            Err(_) => Err(io::ErrorKind::Other.into()),
+        let val = protocol
+            .format()
+            .serialize(&res)
+            .map_err(|kind| CodecError::new(protocol, MessageType::Response, kind))?;
+        self.check_size(protocol, MessageType::Response, val.len())?;
+        write_length_prefixed(io, &val).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::file_exchange::{
+        FileExchangeCodec, FileExchangeProtocol, FileExchangeRequest, FileExchangeResponse,
+        PaymentSchedule, QuoteTerms,
+    };
+    use super::*;
+    use ethers::types::{H256, U256};
+    use futures::io::Cursor;
+    use libp2p::core::upgrade::write_varint;
+    use std::collections::HashSet;
+
+    const FORMATS: [Format; 3] = [Format::Json, Format::Bincode, Format::Cbor];
+
+    fn quote() -> FileExchangeResponse {
+        FileExchangeResponse::Quote {
+            quote_id: 7,
+            root_hash: H256::repeat_byte(1),
+            range: 2..40,
+            size: 40 * 1024,
+            terms: QuoteTerms {
+                chunk_price: U256::from(12),
+                chunk_size: 1024,
+                payment_schedule: PaymentSchedule::Prepaid { window: 4 },
+                valid_until: 1_700_000_000,
+            },
         }
     }
+
+    #[tokio::test]
+    async fn messages_round_trip_in_every_format() {
+        let request = FileExchangeRequest::UpdateRange {
+            process_id: 3,
+            end_sequence_no: 42,
+        };
+        let mut encoded = HashSet::new();
+        for format in FORMATS {
+            let protocol = FileExchangeProtocol(format);
+            let mut codec = FileExchangeCodec::default();
+
+            let mut io = Cursor::new(Vec::new());
+            codec
+                .write_request(&protocol, &mut io, request.clone())
+                .await
+                .unwrap();
+            encoded.insert(io.get_ref().clone());
+            io.set_position(0);
+            let decoded = codec.read_request(&protocol, &mut io).await.unwrap();
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", request));
+
+            let mut io = Cursor::new(Vec::new());
+            codec
+                .write_response(&protocol, &mut io, quote())
+                .await
+                .unwrap();
+            io.set_position(0);
+            let decoded = codec.read_response(&protocol, &mut io).await.unwrap();
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", quote()));
+        }
+        // each format is encoded differently
+        assert_eq!(encoded.len(), FORMATS.len());
+    }
+
+    #[test]
+    fn protocol_names_differ_per_format() {
+        let names: HashSet<_> = FORMATS
+            .iter()
+            .map(|format| FileExchangeProtocol(*format).protocol_name().to_vec())
+            .collect();
+        assert_eq!(names.len(), FORMATS.len());
+    }
+
+    fn too_large(error: io::Error, message_type: MessageType) -> (usize, usize) {
+        match CodecError::find(&error) {
+            Some(CodecError {
+                message_type: t,
+                kind: CodecErrorKind::TooLarge { size, max_size },
+                ..
+            }) if *t == message_type => (*size, *max_size),
+            _ => panic!("expected {} to be too large, got {}", message_type, error),
+        }
+    }
+
+    /// Only the length prefix of a message of `size` bytes
+    async fn length_prefix(size: usize) -> Cursor<Vec<u8>> {
+        let mut io = Cursor::new(Vec::new());
+        write_varint(&mut io, size).await.unwrap();
+        io.set_position(0);
+        io
+    }
+
+    #[tokio::test]
+    async fn oversized_messages_are_rejected_before_reading() {
+        let protocol = FileExchangeProtocol(Format::Json);
+        let mut codec = FileExchangeCodec::new(SizeLimits {
+            request: 64,
+            response: 128,
+        });
+
+        // body is never sent, thus reading it would fail with
+        // an unexpected EOF instead, after allocating 1GB
+        let err = codec
+            .read_request(&protocol, &mut length_prefix(1 << 30).await)
+            .await
+            .unwrap_err();
+        assert_eq!(too_large(err, MessageType::Request), (1 << 30, 64));
+        let err = codec
+            .read_response(&protocol, &mut length_prefix(1 << 30).await)
+            .await
+            .unwrap_err();
+        assert_eq!(too_large(err, MessageType::Response), (1 << 30, 128));
+
+        // writing oversized messages fails without sending them
+        let mut io = Cursor::new(Vec::new());
+        let request = FileExchangeRequest::Accept { quote_id: 1 };
+        let mut small_codec = FileExchangeCodec::new(SizeLimits {
+            request: 4,
+            response: 4,
+        });
+        let err = small_codec
+            .write_request(&protocol, &mut io, request)
+            .await
+            .unwrap_err();
+        too_large(err, MessageType::Request);
+        let err = small_codec
+            .write_response(&protocol, &mut io, quote())
+            .await
+            .unwrap_err();
+        too_large(err, MessageType::Response);
+        assert!(io.get_ref().is_empty());
+    }
+
+    #[tokio::test]
+    async fn protocol_limits_override_default_limits() {
+        let limits = SizeLimits {
+            request: 16,
+            response: 16,
+        };
+        let mut codec = FileExchangeCodec::default()
+            .with_protocol_limits(&FileExchangeProtocol(Format::Cbor), limits);
+
+        // default limits still apply to JSON
+        let json = FileExchangeProtocol(Format::Json);
+        let mut io = Cursor::new(Vec::new());
+        codec.write_response(&json, &mut io, quote()).await.unwrap();
+        io.set_position(0);
+        codec.read_response(&json, &mut io).await.unwrap();
+
+        let cbor = FileExchangeProtocol(Format::Cbor);
+        let err = codec
+            .read_request(&cbor, &mut length_prefix(17).await)
+            .await
+            .unwrap_err();
+        assert_eq!(too_large(err, MessageType::Request), (17, 16));
+        let err = codec
+            .write_response(&cbor, &mut Cursor::new(Vec::new()), quote())
+            .await
+            .unwrap_err();
+        assert_eq!(too_large(err, MessageType::Response).1, 16);
+    }
 }
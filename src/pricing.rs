@@ -0,0 +1,148 @@
+use super::catalog::FileMetadata;
+use super::network::file_exchange::{PaymentSchedule, QuoteTerms};
+use ethers::types::{Address, U256};
+
+/// What a seeder knows while pricing a quote
+pub struct QuoteContext<'a> {
+    pub metadata: &'a FileMetadata,
+    pub requester_address: Address,
+    /// No. of chunks requested
+    pub chunk_count: usize,
+    /// No. of active processes of the seeder
+    pub active_processes: usize,
+    /// Amount requester has paid so far, i.e. charges
+    /// of RFPs it has confirmed
+    pub requester_paid: U256,
+}
+
+/// Decision on a quote or a counter offer
+#[derive(Debug, Clone, PartialEq)]
+pub enum QuoteDecision {
+    Accept,
+    /// Counter with price per chunk
+    Counter(U256),
+    Reject,
+}
+
+/// Decides prices quoted by the seeder
+pub trait SeederPricingPolicy: Send + Sync {
+    /// Price per chunk to quote
+    fn quote(&self, ctx: &QuoteContext) -> U256;
+
+    /// Decides on requester's counter offer of
+    /// `offered` price per chunk to quote of `quoted`
+    fn counter(&self, ctx: &QuoteContext, quoted: U256, offered: U256) -> QuoteDecision;
+
+    /// Schedule on which requester should pay
+    fn payment_schedule(&self, ctx: &QuoteContext) -> PaymentSchedule;
+}
+
+/// Decides whether requester accepts a quote
+pub trait RequesterPricingPolicy: Send + Sync {
+    /// `chunk_count` is the no. of chunks requested and
+    /// `counters` is the no. of counter offers already made
+    /// for the quote
+    fn evaluate(&self, terms: &QuoteTerms, chunk_count: usize, counters: usize) -> QuoteDecision;
+}
+
+/// Prices chunks at the list price of the file with a
+/// surcharge under load and discounts for large requests
+/// and for requesters that have paid before.
+///
+/// Requesters that have paid at least `trusted_min_paid`
+/// are trusted and pay on `trusted_schedule`, rest pay on
+/// `untrusted_schedule`.
+#[derive(Clone)]
+pub struct DefaultSeederPolicy {
+    /// Active processes after which surcharge applies
+    pub load_threshold: usize,
+    pub load_surcharge_percent: u64,
+    /// No. of chunks after which volume discount applies
+    pub volume_threshold: usize,
+    pub volume_discount_percent: u64,
+    pub returning_discount_percent: u64,
+    /// Counter offers of at least this percent
+    /// of the quoted price are accepted
+    pub min_accept_percent: u64,
+    pub trusted_min_paid: U256,
+    pub trusted_schedule: PaymentSchedule,
+    pub untrusted_schedule: PaymentSchedule,
+}
+
+impl Default for DefaultSeederPolicy {
+    fn default() -> Self {
+        Self {
+            load_threshold: 10,
+            load_surcharge_percent: 20,
+            volume_threshold: 1000,
+            volume_discount_percent: 10,
+            returning_discount_percent: 5,
+            min_accept_percent: 90,
+            trusted_min_paid: U256::one(),
+            trusted_schedule: PaymentSchedule::EveryChunks(16),
+            untrusted_schedule: PaymentSchedule::Prepaid { window: 1 },
+        }
+    }
+}
+
+impl SeederPricingPolicy for DefaultSeederPolicy {
+    fn quote(&self, ctx: &QuoteContext) -> U256 {
+        let mut percent = 100;
+        if ctx.active_processes >= self.load_threshold {
+            percent += self.load_surcharge_percent;
+        }
+        if ctx.chunk_count >= self.volume_threshold {
+            percent -= self.volume_discount_percent.min(percent);
+        }
+        if !ctx.requester_paid.is_zero() {
+            percent -= self.returning_discount_percent.min(percent);
+        }
+        ctx.metadata.chunk_price * percent / 100
+    }
+
+    fn counter(&self, _: &QuoteContext, quoted: U256, offered: U256) -> QuoteDecision {
+        let min_price = quoted * self.min_accept_percent / 100;
+        if offered >= min_price {
+            QuoteDecision::Accept
+        } else {
+            QuoteDecision::Counter(min_price)
+        }
+    }
+
+    fn payment_schedule(&self, ctx: &QuoteContext) -> PaymentSchedule {
+        if ctx.requester_paid >= self.trusted_min_paid {
+            self.trusted_schedule.clone()
+        } else {
+            self.untrusted_schedule.clone()
+        }
+    }
+}
+
+/// Accepts quotes up to `max_chunk_price`. Counters
+/// pricier ones with `max_chunk_price` at most
+/// `max_counters` times. Rejects quotes that require
+/// prepaying for more than `max_prepaid_window` chunks.
+#[derive(Clone)]
+pub struct MaxPricePolicy {
+    pub max_chunk_price: U256,
+    pub max_counters: usize,
+    pub max_prepaid_window: usize,
+}
+
+impl RequesterPricingPolicy for MaxPricePolicy {
+    fn evaluate(&self, terms: &QuoteTerms, _: usize, counters: usize) -> QuoteDecision {
+        if let PaymentSchedule::Prepaid { window } = terms.payment_schedule {
+            if window > self.max_prepaid_window {
+                return QuoteDecision::Reject;
+            }
+        }
+
+        if terms.chunk_price <= self.max_chunk_price {
+            QuoteDecision::Accept
+        } else if counters < self.max_counters {
+            QuoteDecision::Counter(self.max_chunk_price)
+        } else {
+            QuoteDecision::Reject
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// State of a file transfer process. Used by both
+/// seeder (`SProcess`) and requester (`RProcess`).
+///
+/// Negotiating -> Transferring | AwaitingPayment
+/// Transferring <-> AwaitingPayment
+/// Transferring | AwaitingPayment -> Completed | Disputed
+/// Disputed -> Transferring | AwaitingPayment
+///
+/// Any non terminal state can move to Aborted.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ProcessState {
+    /// Quote has been accepted, but
+    /// transfer hasn't started yet
+    Negotiating,
+    /// Chunks are being sent
+    Transferring,
+    /// RFP has been sent and chunks are
+    /// held back until it is confirmed
+    AwaitingPayment,
+    /// All chunks sent and paid for
+    Completed,
+    Aborted {
+        reason: String,
+    },
+    /// Counterparty misbehaved (e.g. sent invalid
+    /// receipt). Process is halted until resumed.
+    Disputed,
+}
+
+impl ProcessState {
+    pub fn can_transition_to(&self, next: &ProcessState) -> bool {
+        use ProcessState::*;
+        match (self, next) {
+            (Completed | Aborted { .. }, _) => false,
+            (_, Aborted { .. }) => true,
+            (Negotiating, Transferring | AwaitingPayment) => true,
+            (Transferring, AwaitingPayment | Completed | Disputed) => true,
+            (AwaitingPayment, Transferring | Completed | Disputed) => true,
+            (Disputed, Transferring | AwaitingPayment) => true,
+            _ => false,
+        }
+    }
+
+    /// Moves to `next` state. Fails if the
+    /// transition isn't allowed.
+    pub fn transition(&mut self, next: ProcessState) -> anyhow::Result<()> {
+        if !self.can_transition_to(&next) {
+            return Err(anyhow::anyhow!(
+                "Invalid process state transition from {} to {}",
+                self,
+                next
+            ));
+        }
+        *self = next;
+        Ok(())
+    }
+
+    /// Whether process has ended
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, ProcessState::Completed | ProcessState::Aborted { .. })
+    }
+}
+
+impl fmt::Display for ProcessState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessState::Negotiating => write!(f, "negotiating"),
+            ProcessState::Transferring => write!(f, "transferring"),
+            ProcessState::AwaitingPayment => write!(f, "awaiting payment"),
+            ProcessState::Completed => write!(f, "completed"),
+            ProcessState::Aborted { reason } => write!(f, "aborted ({})", reason),
+            ProcessState::Disputed => write!(f, "disputed"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProcessState::*;
+    use super::*;
+
+    fn aborted() -> ProcessState {
+        Aborted {
+            reason: "test".into(),
+        }
+    }
+
+    fn all() -> Vec<ProcessState> {
+        vec![
+            Negotiating,
+            Transferring,
+            AwaitingPayment,
+            Completed,
+            aborted(),
+            Disputed,
+        ]
+    }
+
+    #[test]
+    fn allowed_transitions() {
+        let allowed = [
+            (Negotiating, Transferring),
+            (Negotiating, AwaitingPayment),
+            (Transferring, AwaitingPayment),
+            (AwaitingPayment, Transferring),
+            (Transferring, Completed),
+            (AwaitingPayment, Completed),
+            (Transferring, Disputed),
+            (AwaitingPayment, Disputed),
+            (Disputed, Transferring),
+            (Disputed, AwaitingPayment),
+        ];
+        for (mut from, to) in allowed {
+            assert!(from.transition(to.clone()).is_ok(), "{} -> {}", from, to);
+            assert_eq!(from, to);
+        }
+        for from in [Negotiating, Transferring, AwaitingPayment, Disputed] {
+            assert!(from.can_transition_to(&aborted()), "{} -> aborted", from);
+        }
+    }
+
+    #[test]
+    fn forbidden_transitions() {
+        for to in all() {
+            assert!(!Completed.can_transition_to(&to), "completed -> {}", to);
+            assert!(!aborted().can_transition_to(&to), "aborted -> {}", to);
+        }
+        let forbidden = [
+            (Negotiating, Negotiating),
+            (Negotiating, Completed),
+            (Negotiating, Disputed),
+            (Transferring, Negotiating),
+            (Transferring, Transferring),
+            (AwaitingPayment, Negotiating),
+            (AwaitingPayment, AwaitingPayment),
+            (Disputed, Negotiating),
+            (Disputed, Completed),
+            (Disputed, Disputed),
+        ];
+        for (mut from, to) in forbidden {
+            let before = from.clone();
+            assert!(from.transition(to.clone()).is_err(), "{} -> {}", from, to);
+            assert_eq!(from, before);
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use super::network::ban_list::{Ban, BanList};
+use super::storage::Storage;
+use ethers::types::Address;
+use libp2p::PeerId;
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Penalty at which peer is banned temporarily
+const TEMPORARY_BAN_PENALTY: u32 = 6;
+/// Penalty at which peer is banned permanently
+const PERMANENT_BAN_PENALTY: u32 = 30;
+/// Secs a temporary ban lasts
+const TEMPORARY_BAN_DURATION: u64 = 60 * 60;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ReputationEvent {
+    CompletedTransfer,
+    /// Requester refused to pay for an RFP
+    UnpaidRfp,
+    /// Seeder sent a chunk that failed verification
+    InvalidChunk,
+    /// Peer sent a receipt (or RFP) that
+    /// doesn't match the agreed terms
+    InvalidReceipt,
+}
+
+/// Reputations are kept for both peer id and
+/// the address peer has attested to, so that
+/// peer can't escape a ban by changing either
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReputationKey {
+    Peer(PeerId),
+    Address(Address),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Reputation {
+    pub completed_transfers: u32,
+    pub unpaid_rfps: u32,
+    pub invalid_chunks: u32,
+    pub invalid_receipts: u32,
+    pub ban: Option<Ban>,
+}
+
+impl Reputation {
+    /// Invalid chunks and receipts are worse than unpaid
+    /// RFPs, since the latter can be caused by failures
+    /// on requester's end
+    fn offences(&self) -> u32 {
+        self.unpaid_rfps + 3 * (self.invalid_chunks + self.invalid_receipts)
+    }
+
+    /// Penalty for misbehaviour. Completed
+    /// transfers earn some leeway.
+    fn penalty(&self) -> u32 {
+        self.offences()
+            .saturating_sub(self.completed_transfers / 10)
+    }
+
+    /// Standing in (0, 1]. Lower for peers that have
+    /// misbehaved relative to transfers completed.
+    pub fn standing(&self) -> f64 {
+        let good = self.completed_transfers as f64 + 1.0;
+        good / (good + self.offences() as f64)
+    }
+
+    /// Records `event` and returns the ban
+    /// imposed because of it, if any
+    pub fn record(&mut self, event: ReputationEvent, now: u64) -> Option<Ban> {
+        match event {
+            ReputationEvent::CompletedTransfer => {
+                self.completed_transfers += 1;
+                return None;
+            }
+            ReputationEvent::UnpaidRfp => self.unpaid_rfps += 1,
+            ReputationEvent::InvalidChunk => self.invalid_chunks += 1,
+            ReputationEvent::InvalidReceipt => self.invalid_receipts += 1,
+        }
+
+        let penalty = self.penalty();
+        let ban = if penalty >= PERMANENT_BAN_PENALTY {
+            Ban::Permanent
+        } else if penalty >= TEMPORARY_BAN_PENALTY && !self.is_banned(now) {
+            Ban::Until(now + TEMPORARY_BAN_DURATION)
+        } else {
+            return None;
+        };
+        self.ban = Some(ban);
+        Some(ban)
+    }
+
+    pub fn is_banned(&self, now: u64) -> bool {
+        self.ban.is_some_and(|b| b.is_active(now))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Records behaviour of peers in storage
+/// and bans the ones misbehaving
+#[derive(Clone)]
+pub struct Reputations {
+    storage: Storage,
+    ban_list: BanList,
+}
+
+impl Reputations {
+    /// Restores bans of peers that are still
+    /// active from storage into `ban_list`
+    pub fn new(storage: Storage, ban_list: BanList) -> Self {
+        let now = now();
+        let reputations = storage.get_all_reputations().unwrap_or_default();
+        for (key, reputation) in reputations {
+            if let (ReputationKey::Peer(peer_id), Some(ban)) = (key, reputation.ban) {
+                if ban.is_active(now) {
+                    ban_list.ban(peer_id, ban);
+                }
+            }
+        }
+        Self { storage, ban_list }
+    }
+
+    /// Keys of `peer_id`, including the
+    /// address it has attested to
+    fn keys(&self, peer_id: &PeerId) -> Vec<ReputationKey> {
+        let mut keys = vec![ReputationKey::Peer(*peer_id)];
+        if let Ok(Some(attestation)) = self.storage.find_peer_attestation(peer_id) {
+            keys.push(ReputationKey::Address(attestation.address));
+        }
+        keys
+    }
+
+    /// Records `event` for `peer_id` and bans
+    /// it if it has misbehaved enough
+    pub fn record(&self, peer_id: PeerId, event: ReputationEvent) {
+        let now = now();
+        for key in self.keys(&peer_id) {
+            match self
+                .storage
+                .update_reputation(key, |reputation| reputation.record(event, now))
+            {
+                Ok(Some(ban)) => {
+                    warn!(
+                        "(reputation) banned {:?} of peer {:?} with {:?}",
+                        key, peer_id, ban
+                    );
+                    self.ban_list.ban(peer_id, ban);
+                }
+                Ok(None) => {}
+                Err(e) => error!(
+                    "(reputation) failed to store reputation of {:?} with error {}",
+                    key, e
+                ),
+            }
+        }
+    }
+
+    /// Standing of `peer_id`. Lowest of the peer
+    /// id and the address it attested to.
+    pub fn standing(&self, peer_id: &PeerId) -> f64 {
+        self.keys(peer_id)
+            .iter()
+            .filter_map(|k| self.storage.find_reputation(k).ok().flatten())
+            .map(|r| r.standing())
+            .fold(1.0, f64::min)
+    }
+
+    /// Whether `peer_id`, or the address
+    /// it attested to, is banned
+    pub fn is_banned(&self, peer_id: &PeerId) -> bool {
+        self.ban_list.is_banned(peer_id) || self.keys(peer_id).iter().any(|k| self.is_key_banned(k))
+    }
+
+    /// Whether `address` is banned, regardless
+    /// of the peers that attested to it
+    pub fn is_address_banned(&self, address: &Address) -> bool {
+        self.is_key_banned(&ReputationKey::Address(*address))
+    }
+
+    fn is_key_banned(&self, key: &ReputationKey) -> bool {
+        self.storage
+            .find_reputation(key)
+            .ok()
+            .flatten()
+            .is_some_and(|r| r.is_banned(now()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn misbehaving_peer_is_banned() {
+        let mut reputation = Reputation::default();
+        assert_eq!(reputation.record(ReputationEvent::InvalidChunk, 0), None);
+        assert_eq!(
+            reputation.record(ReputationEvent::InvalidReceipt, 0),
+            Some(Ban::Until(TEMPORARY_BAN_DURATION))
+        );
+        assert!(reputation.is_banned(0));
+        assert!(!reputation.is_banned(TEMPORARY_BAN_DURATION));
+
+        for _ in 0..8 {
+            reputation.record(ReputationEvent::InvalidChunk, TEMPORARY_BAN_DURATION);
+        }
+        assert_eq!(reputation.ban, Some(Ban::Permanent));
+    }
+
+    #[test]
+    fn completed_transfers_earn_leeway() {
+        let mut reputation = Reputation::default();
+        for _ in 0..20 {
+            reputation.record(ReputationEvent::CompletedTransfer, 0);
+        }
+        for _ in 0..7 {
+            assert_eq!(reputation.record(ReputationEvent::UnpaidRfp, 0), None);
+        }
+        assert!(reputation.record(ReputationEvent::UnpaidRfp, 0).is_some());
+    }
+
+    #[test]
+    fn ban_of_address_applies_to_new_peers() {
+        let dir = std::env::temp_dir().join(format!("dse-reputation-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let reputations = Reputations::new(Storage::open(&dir), BanList::default());
+        let address = Address::random();
+        reputations
+            .storage
+            .update_reputation(ReputationKey::Address(address), |r| {
+                r.ban = Some(Ban::Permanent)
+            })
+            .unwrap();
+
+        assert!(reputations.is_address_banned(&address));
+        assert!(!reputations.is_address_banned(&Address::random()));
+        assert!(!reputations.is_banned(&PeerId::random()));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,12 +1,29 @@
+use super::catalog::FileMetadata;
+use super::file_requester::{Download, RProcess};
 use super::file_seeder::SProcess;
-use super::wallet::ReceiptWithSignatures;
-use ethers::types::Address;
+use super::reputation::{Reputation, ReputationKey};
+use super::wallet::{Attestation, ReceiptWithSignatures};
+use ethers::types::{Address, H256};
+use libp2p::PeerId;
 use rocksdb::DB;
+use serde::{de::DeserializeOwned, Serialize};
 use std::{
     collections::HashMap,
+    hash::Hash,
+    path::Path,
     sync::{Arc, Mutex},
 };
 
+/// Prefixes of maps stored in cache DB.
+/// None of them is a prefix of another.
+const ACTIVE_SPROCESSES: &[u8] = b"active-processes/";
+const ACTIVE_RPROCESSES: &[u8] = b"active-rprocesses/";
+const ACTIVE_DOWNLOADS: &[u8] = b"active-downloads/";
+const RECEIVED_CHUNKS: &[u8] = b"received-chunks/";
+const PEER_ATTESTATIONS: &[u8] = b"peer-attestations/";
+const REPUTATIONS: &[u8] = b"reputations/";
+const CATALOG: &[u8] = b"catalog/";
+
 #[derive(Clone)]
 pub struct Storage {
     /// Stores receipts actively used with other
@@ -22,14 +39,19 @@ pub struct Storage {
 
 impl Storage {
     pub fn new() -> Self {
+        Self::open(Path::new("./dbs"))
+    }
+
+    /// Opens DBs in `dir`
+    pub fn open(dir: &Path) -> Self {
         let active_receipts = Arc::new(Mutex::new(
-            DB::open_default("./dbs/active_receipts").expect("Failed to open DB"),
+            DB::open_default(dir.join("active_receipts")).expect("Failed to open DB"),
         ));
         let old_receipts = Arc::new(Mutex::new(
-            DB::open_default("./dbs/old_receipts").expect("Failed to open DB"),
+            DB::open_default(dir.join("old_receipts")).expect("Failed to open DB"),
         ));
         let cache = Arc::new(Mutex::new(
-            DB::open_default("./dbs/cache").expect("Failed to open DB"),
+            DB::open_default(dir.join("cache")).expect("Failed to open DB"),
         ));
 
         Self {
@@ -65,26 +87,310 @@ impl Storage {
 
     // get active `SProcess`es
     pub fn get_all_active_sprocess(&self) -> anyhow::Result<HashMap<u32, SProcess>> {
-        let db = self.cache.lock().unwrap();
-        db.get(b"active-processes")
-            .map_err(|e| e.into())
-            .and_then(|r| {
-                if let Some(r) = r {
-                    bincode::deserialize::<HashMap<u32, SProcess>>(&r).map_err(|e| e.into())
-                } else {
-                    Err(anyhow::anyhow!("Record does not exists"))
-                }
-            })
+        self.get_entries(ACTIVE_SPROCESSES)
     }
 
     // update active `SProcess`
     pub fn update_active_sprocess(&self, process: SProcess) -> anyhow::Result<()> {
-        let mut map = self.get_all_active_sprocess()?;
-        map.insert(process.id, process);
+        self.put_entry(ACTIVE_SPROCESSES, &process.id, &process)
+    }
+
+    // remove `SProcess` from active ones
+    pub fn remove_active_sprocess(&self, id: u32) -> anyhow::Result<()> {
+        self.delete_entry(ACTIVE_SPROCESSES, &id)
+    }
+
+    // get active `RProcess`es keyed by seeder's peer id
+    // and process id, since process ids are assigned by seeders
+    pub fn get_all_active_rprocess(&self) -> anyhow::Result<HashMap<(PeerId, u32), RProcess>> {
+        self.get_entries(ACTIVE_RPROCESSES)
+    }
+
+    // find active `RProcess` of seeder
+    pub fn find_active_rprocess(
+        &self,
+        seeder_peer_id: &PeerId,
+        id: u32,
+    ) -> anyhow::Result<Option<RProcess>> {
+        self.get_entry(ACTIVE_RPROCESSES, &(*seeder_peer_id, id))
+    }
+
+    // update active `RProcess`
+    pub fn update_active_rprocess(&self, process: RProcess) -> anyhow::Result<()> {
+        self.put_entry(
+            ACTIVE_RPROCESSES,
+            &(process.seeder_peer_id, process.id),
+            &process,
+        )
+    }
+
+    // remove `RProcess` from active ones
+    pub fn remove_active_rprocess(&self, seeder_peer_id: &PeerId, id: u32) -> anyhow::Result<()> {
+        self.delete_entry(ACTIVE_RPROCESSES, &(*seeder_peer_id, id))
+    }
+
+    // get active downloads
+    pub fn get_all_active_downloads(&self) -> anyhow::Result<HashMap<H256, Download>> {
+        self.get_entries(ACTIVE_DOWNLOADS)
+    }
+
+    // find active download of file
+    pub fn find_active_download(&self, root_hash: &H256) -> anyhow::Result<Option<Download>> {
+        self.get_entry(ACTIVE_DOWNLOADS, root_hash)
+    }
+
+    // update active download
+    pub fn update_active_download(&self, download: Download) -> anyhow::Result<()> {
+        self.put_entry(ACTIVE_DOWNLOADS, &download.root_hash, &download)
+    }
+
+    // remove download from active ones, along with its received chunks
+    pub fn remove_active_download(&self, root_hash: &H256) -> anyhow::Result<()> {
+        self.remove_received_chunks(root_hash)?;
+        self.delete_entry(ACTIVE_DOWNLOADS, root_hash)
+    }
+
+    // get words of bitmap of chunks received of file, keyed by
+    // word index. Words without any chunk received are missing.
+    pub fn get_received_chunks(&self, root_hash: &H256) -> anyhow::Result<HashMap<u32, u64>> {
+        self.get_entries(&entry_key(RECEIVED_CHUNKS, root_hash)?)
+    }
+
+    // store word at `index` of bitmap of chunks received of file
+    pub fn store_received_chunks(
+        &self,
+        root_hash: &H256,
+        index: u32,
+        word: u64,
+    ) -> anyhow::Result<()> {
+        self.put_entry(RECEIVED_CHUNKS, &(*root_hash, index), &word)
+    }
+
+    // remove bitmap of chunks received of file
+    pub fn remove_received_chunks(&self, root_hash: &H256) -> anyhow::Result<()> {
+        let indices = self.get_received_chunks(root_hash)?.into_keys();
+        for index in indices {
+            self.delete_entry(RECEIVED_CHUNKS, &(*root_hash, index))?;
+        }
+        Ok(())
+    }
+
+    // find verified attestation of peer
+    pub fn find_peer_attestation(&self, peer_id: &PeerId) -> anyhow::Result<Option<Attestation>> {
+        self.get_entry(PEER_ATTESTATIONS, peer_id)
+    }
+
+    // store verified attestation of a peer
+    pub fn store_peer_attestation(&self, attestation: &Attestation) -> anyhow::Result<()> {
+        self.put_entry(PEER_ATTESTATIONS, &attestation.peer_id, attestation)
+    }
+
+    /// Checks that `peer_id` has attested to `address`
+    pub fn ensure_peer_address(&self, peer_id: &PeerId, address: &Address) -> anyhow::Result<()> {
+        match self.find_peer_attestation(peer_id)? {
+            Some(a) if a.address == *address => Ok(()),
+            _ => Err(anyhow::anyhow!(
+                "Peer {} has not attested to address {:?}",
+                peer_id,
+                address
+            )),
+        }
+    }
+
+    // get reputations of peers
+    pub fn get_all_reputations(&self) -> anyhow::Result<HashMap<ReputationKey, Reputation>> {
+        self.get_entries(REPUTATIONS)
+    }
+
+    // find reputation of peer
+    pub fn find_reputation(&self, key: &ReputationKey) -> anyhow::Result<Option<Reputation>> {
+        self.get_entry(REPUTATIONS, key)
+    }
+
+    // update reputation of peer with `f` atomically
+    pub fn update_reputation<R>(
+        &self,
+        key: ReputationKey,
+        f: impl FnOnce(&mut Reputation) -> R,
+    ) -> anyhow::Result<R> {
+        let key = entry_key(REPUTATIONS, &key)?;
+        let db = self.cache.lock().unwrap();
+        let mut reputation = match db.get(&key)? {
+            Some(v) => bincode::deserialize(&v)?,
+            None => Reputation::default(),
+        };
+        let result = f(&mut reputation);
+        db.put(key, bincode::serialize(&reputation)?)?;
+        Ok(result)
+    }
+
+    /// Returns next id to use for a new process
+    pub fn next_process_id(&self) -> anyhow::Result<u32> {
+        let db = self.cache.lock().unwrap();
+        let id = match db.get(b"next-process-id")? {
+            Some(r) => bincode::deserialize::<u32>(&r)?,
+            None => 0,
+        };
+        db.put(b"next-process-id", bincode::serialize(&(id + 1))?)?;
+        Ok(id)
+    }
+
+    // get metadata of all files in catalog
+    pub fn get_all_file_metadata(&self) -> anyhow::Result<HashMap<H256, FileMetadata>> {
+        self.get_entries(CATALOG)
+    }
+
+    // add file metadata to catalog
+    pub fn add_file_metadata(&self, metadata: &FileMetadata) -> anyhow::Result<()> {
+        self.put_entry(CATALOG, &metadata.root_hash, metadata)
+    }
+
+    // remove file metadata from catalog
+    pub fn remove_file_metadata(&self, root_hash: &H256) -> anyhow::Result<()> {
+        self.delete_entry(CATALOG, root_hash)
+    }
+
+    /// All entries of map stored under `prefix`
+    fn get_entries<K, V>(&self, prefix: &[u8]) -> anyhow::Result<HashMap<K, V>>
+    where
+        K: DeserializeOwned + Eq + Hash,
+        V: DeserializeOwned,
+    {
+        let db = self.cache.lock().unwrap();
+        db.prefix_iterator(prefix)
+            .take_while(|(k, _)| k.starts_with(prefix))
+            .map(|(k, v)| {
+                Ok((
+                    bincode::deserialize(&k[prefix.len()..])?,
+                    bincode::deserialize(&v)?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Entry with `key` of map stored under `prefix`
+    fn get_entry<K: Serialize, V: DeserializeOwned>(
+        &self,
+        prefix: &[u8],
+        key: &K,
+    ) -> anyhow::Result<Option<V>> {
+        let key = entry_key(prefix, key)?;
+        let db = self.cache.lock().unwrap();
+        match db.get(key)? {
+            Some(v) => Ok(Some(bincode::deserialize(&v)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn put_entry<K: Serialize, V: Serialize>(
+        &self,
+        prefix: &[u8],
+        key: &K,
+        value: &V,
+    ) -> anyhow::Result<()> {
+        let key = entry_key(prefix, key)?;
+        let value = bincode::serialize(value)?;
         let db = self.cache.lock().unwrap();
-        db.put(b"active-processes", bincode::serialize(&map)?)?;
+        db.put(key, value)?;
         Ok(())
     }
 
-    //
+    fn delete_entry<K: Serialize>(&self, prefix: &[u8], key: &K) -> anyhow::Result<()> {
+        let key = entry_key(prefix, key)?;
+        let db = self.cache.lock().unwrap();
+        db.delete(key)?;
+        Ok(())
+    }
+}
+
+/// Each entry of a map is stored under its own key, i.e.
+/// map's prefix followed by the serialized entry key, so that
+/// updating an entry neither rewrites nor races with the rest.
+fn entry_key<K: Serialize>(prefix: &[u8], key: &K) -> anyhow::Result<Vec<u8>> {
+    let mut entry_key = prefix.to_vec();
+    entry_key.extend(bincode::serialize(key)?);
+    Ok(entry_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reputation::ReputationEvent;
+    use std::{fs, path::PathBuf};
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dse-storage-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn entries_are_updated_independently() {
+        let dir = dir("entries");
+        let storage = Storage::open(&dir);
+        let (a, b) = (
+            ReputationKey::Peer(PeerId::random()),
+            ReputationKey::Peer(PeerId::random()),
+        );
+        for key in [a, a, b] {
+            storage
+                .update_reputation(key, |r| r.record(ReputationEvent::CompletedTransfer, 0))
+                .unwrap();
+        }
+        let reputations = storage.get_all_reputations().unwrap();
+        assert_eq!(reputations.len(), 2);
+        assert_eq!(reputations[&a].completed_transfers, 2);
+        assert_eq!(
+            storage
+                .find_reputation(&b)
+                .unwrap()
+                .unwrap()
+                .completed_transfers,
+            1
+        );
+        // other maps are unaffected
+        assert!(storage.get_all_file_metadata().unwrap().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_entries_are_reported() {
+        let dir = dir("corrupt");
+        let storage = Storage::open(&dir);
+        let key = ReputationKey::Peer(PeerId::random());
+        storage
+            .cache
+            .lock()
+            .unwrap()
+            .put(entry_key(REPUTATIONS, &key).unwrap(), [1u8])
+            .unwrap();
+        assert!(storage.get_all_reputations().is_err());
+        assert!(storage.find_reputation(&key).is_err());
+        assert!(storage.update_reputation(key, |_| ()).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn received_chunks_are_kept_per_file() {
+        let dir = dir("received");
+        let storage = Storage::open(&dir);
+        let (a, b) = (H256::repeat_byte(1), H256::repeat_byte(2));
+        storage.store_received_chunks(&a, 0, 0b101).unwrap();
+        storage.store_received_chunks(&a, 3, u64::MAX).unwrap();
+        storage.store_received_chunks(&a, 0, 0b111).unwrap();
+        storage.store_received_chunks(&b, 1, 1).unwrap();
+        assert_eq!(
+            storage.get_received_chunks(&a).unwrap(),
+            HashMap::from([(0, 0b111), (3, u64::MAX)])
+        );
+
+        storage.remove_received_chunks(&a).unwrap();
+        assert!(storage.get_received_chunks(&a).unwrap().is_empty());
+        assert_eq!(
+            storage.get_received_chunks(&b).unwrap(),
+            HashMap::from([(1, 1)])
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
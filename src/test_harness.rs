@@ -0,0 +1,671 @@
+//! Nodes running in one process on libp2p's `MemoryTransport`,
+//! for testing seeders and requesters end to end.
+use super::bandwidth::BandwidthLimits;
+use super::catalog::Catalog;
+use super::file_requester::FileRequester;
+use super::file_seeder::FileSeeder;
+use super::network::{Network, NetworkConfig};
+use super::pricing::{DefaultSeederPolicy, MaxPricePolicy};
+use super::storage::Storage;
+use super::wallet::Wallet;
+use ethers::{
+    signers::{LocalWallet, Signer},
+    types::{Address, H256, U256},
+    utils::keccak256,
+};
+use libp2p::{
+    core::{
+        muxing::StreamMuxerBox,
+        transport::{upgrade::Version, Boxed, MemoryTransport},
+    },
+    identity::{ed25519, Keypair},
+    multiaddr::Protocol,
+    noise,
+    yamux::YamuxConfig,
+    Multiaddr, PeerId, Transport,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tokio::task::JoinHandle;
+
+/// Memory addresses and directories are unique across
+/// harnesses, since tests run in the same process
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Balance each wallet starts with
+pub const INITIAL_BALANCE: u64 = 1_000_000;
+
+/// In-memory transport upgraded the same
+/// way as the TCP one
+pub fn build_memory_transport(keypair: &Keypair) -> Boxed<(PeerId, StreamMuxerBox)> {
+    let noise_keys = noise::Keypair::<noise::X25519>::new()
+        .into_authentic(keypair)
+        .unwrap();
+    MemoryTransport
+        .upgrade(Version::V1)
+        .authenticate(noise::NoiseConfig::xx(noise_keys).into_authenticated())
+        .multiplex(YamuxConfig::default())
+        .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+        .boxed()
+}
+
+/// Directory removed once dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!("dse-harness-{}-{}", std::process::id(), id));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Roles a node runs on its network
+#[derive(Default)]
+pub struct Roles {
+    pub seeder: Option<DefaultSeederPolicy>,
+    /// Upload limits of seeder
+    pub bandwidth: BandwidthLimits,
+    pub requester: Option<MaxPricePolicy>,
+    /// Files requester downloads along with
+    /// seeders to download them from
+    pub downloads: Vec<(H256, Vec<(PeerId, Multiaddr)>)>,
+}
+
+/// A node of the harness. Keys are derived from
+/// its index, thus peer ids and addresses are
+/// the same across runs. Node can be stopped and
+/// started again with the same storage, to test
+/// recovery after restarts.
+pub struct TestNode {
+    pub peer_id: PeerId,
+    pub multiaddr: Multiaddr,
+    pub address: Address,
+    pub storage: Storage,
+    pub dir: TempDir,
+    keypair: Keypair,
+    signer: LocalWallet,
+    roles: Roles,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl TestNode {
+    pub fn new(index: u8) -> Self {
+        let dir = TempDir::new();
+        let secret = ed25519::SecretKey::from_bytes([index + 1; 32]).unwrap();
+        let keypair = Keypair::Ed25519(secret.into());
+        let signer = LocalWallet::from(
+            ethers::core::k256::ecdsa::SigningKey::from_bytes(&[index + 1; 32]).unwrap(),
+        );
+        Self {
+            peer_id: keypair.public().to_peer_id(),
+            multiaddr: Multiaddr::empty()
+                .with(Protocol::Memory(NEXT_ID.fetch_add(1, Ordering::Relaxed))),
+            address: signer.address(),
+            storage: Storage::open(&dir.path().join("dbs")),
+            dir,
+            keypair,
+            signer,
+            roles: Roles::default(),
+            tasks: Vec::new(),
+        }
+    }
+
+    /// Address to dial the node on
+    pub fn dial_info(&self) -> (PeerId, Multiaddr) {
+        (self.peer_id, self.multiaddr.clone())
+    }
+
+    /// Path `root_hash` is downloaded to
+    pub fn download_path(&self, root_hash: &H256) -> PathBuf {
+        self.dir
+            .path()
+            .join("downloads")
+            .join(format!("{:x}", root_hash))
+    }
+
+    /// Adds `files` to the catalog with `chunk_size`
+    /// and `chunk_price`. Returns their root hashes.
+    pub fn add_files(&self, files: &[&[u8]], chunk_size: usize, chunk_price: U256) -> Vec<H256> {
+        let catalog = Catalog::new(self.storage.clone());
+        files
+            .iter()
+            .map(|data| {
+                let path = self
+                    .dir
+                    .path()
+                    .join(format!("file-{:x}", H256::from(keccak256(data))));
+                fs::write(&path, data).unwrap();
+                catalog.add_file(&path, chunk_size, chunk_price).unwrap()
+            })
+            .collect()
+    }
+
+    /// Starts network of the node running `roles`
+    pub async fn start(&mut self, roles: Roles) {
+        self.roles = roles;
+        let config = NetworkConfig {
+            enable_mdns: false,
+            ..Default::default()
+        };
+        let network = Network::with_transport(
+            self.keypair.clone(),
+            build_memory_transport(&self.keypair),
+            self.multiaddr.clone(),
+            config,
+        )
+        .await
+        .unwrap();
+
+        if let Some(policy) = self.roles.seeder.clone() {
+            let mut seeder = FileSeeder::new(
+                self.storage.clone(),
+                self.wallet(),
+                Box::new(policy),
+                Catalog::new(self.storage.clone()),
+                &network,
+            );
+            seeder.bandwidth().set_limits(self.roles.bandwidth.clone());
+            self.tasks
+                .push(tokio::spawn(async move { seeder.run().await }));
+        }
+        if let Some(policy) = self.roles.requester.clone() {
+            let download_dir = self.dir.path().join("downloads");
+            fs::create_dir_all(&download_dir).unwrap();
+            let mut requester = FileRequester::new(
+                self.storage.clone(),
+                self.wallet(),
+                Box::new(policy),
+                download_dir,
+                &network,
+            );
+            // network has to run for requests to be sent
+            let downloads = std::mem::take(&mut self.roles.downloads);
+            self.tasks.push(tokio::spawn(network.run()));
+            for (root_hash, seeders) in downloads {
+                let seeders = seeders.into_iter().map(|(p, a)| (p, Some(a))).collect();
+                requester.download(root_hash, seeders).unwrap();
+            }
+            self.tasks
+                .push(tokio::spawn(async move { requester.run().await }));
+        } else {
+            self.tasks.push(tokio::spawn(network.run()));
+        }
+    }
+
+    /// Stops network and roles of the node.
+    /// Storage is kept as is.
+    pub async fn stop(&mut self) {
+        for task in self.tasks.drain(..) {
+            task.abort();
+            let _ = task.await;
+        }
+    }
+
+    /// Stops the node and starts it again with
+    /// the same roles. Downloads are recovered
+    /// from storage rather than started again.
+    pub async fn restart(&mut self) {
+        self.stop().await;
+        let roles = std::mem::take(&mut self.roles);
+        self.start(roles).await;
+    }
+
+    /// Amount `payer` has paid to `payee` as per
+    /// active receipt stored by the node
+    pub fn paid(&self, payer: &Address, payee: &Address) -> U256 {
+        let other = if payer == &self.address { payee } else { payer };
+        self.storage
+            .find_active_receipt(other)
+            .map(|r| r.receipt().owes(payer))
+            .unwrap_or_default()
+    }
+
+    fn wallet(&self) -> Wallet {
+        Wallet::new(
+            self.storage.clone(),
+            self.signer.clone(),
+            U256::from(INITIAL_BALANCE),
+        )
+    }
+}
+
+impl Drop for TestNode {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::file_exchange::PaymentSchedule;
+    use crate::reputation::ReputationKey;
+    use std::{os::unix::fs::FileExt, time::Duration};
+
+    /// Polls `f` till it returns true, for at most 30s. Tests
+    /// run with paused time, thus polling advances the clock
+    /// of the runtime only once all nodes are idle, and
+    /// the outcome does not depend on the speed of the host.
+    async fn wait_for(mut f: impl FnMut() -> bool) -> bool {
+        for _ in 0..300 {
+            if f() {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        false
+    }
+
+    /// Limits of seeders that take a few seconds
+    /// to send files, thus nodes can be restarted
+    /// in the middle of a transfer
+    const THROTTLED: BandwidthLimits = BandwidthLimits {
+        global: Some(4096),
+        per_peer: None,
+        per_process: None,
+    };
+
+    /// Requester accepting `chunk_price`
+    fn requester_policy(max_chunk_price: U256) -> MaxPricePolicy {
+        MaxPricePolicy {
+            max_chunk_price,
+            max_counters: 0,
+            max_prepaid_window: 1,
+        }
+    }
+
+    fn data(chunk_count: usize, chunk_size: usize, seed: usize) -> Vec<u8> {
+        (0..chunk_count * chunk_size + chunk_size / 3)
+            .map(|i| (i * seed) as u8)
+            .collect()
+    }
+
+    /// Waits till `node` has received `root_hash`
+    /// and returns the received file
+    async fn downloaded(node: &TestNode, root_hash: &H256) -> Vec<u8> {
+        let path = node.download_path(root_hash);
+        // file is created once download starts
+        assert!(
+            wait_for(|| path.exists()
+                && node
+                    .storage
+                    .find_active_download(root_hash)
+                    .is_ok_and(|d| d.is_none()))
+            .await,
+            "download did not complete"
+        );
+        fs::read(path).unwrap()
+    }
+
+    /// Starts seeder with `data` on node at `index`
+    async fn seeder(
+        index: u8,
+        data: &[u8],
+        chunk_size: usize,
+        chunk_price: U256,
+        policy: DefaultSeederPolicy,
+    ) -> (TestNode, H256) {
+        let mut node = TestNode::new(index);
+        let root_hash = node.add_files(&[data], chunk_size, chunk_price)[0];
+        node.start(Roles {
+            seeder: Some(policy),
+            ..Default::default()
+        })
+        .await;
+        (node, root_hash)
+    }
+
+    /// Starts requester downloading `root_hash`
+    /// from `seeders` on node at `index`
+    async fn requester(
+        index: u8,
+        root_hash: H256,
+        seeders: &[&TestNode],
+        max_chunk_price: U256,
+    ) -> TestNode {
+        let mut node = TestNode::new(index);
+        node.start(Roles {
+            requester: Some(requester_policy(max_chunk_price)),
+            downloads: vec![(root_hash, seeders.iter().map(|s| s.dial_info()).collect())],
+            ..Default::default()
+        })
+        .await;
+        node
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn file_is_transferred_with_payments() {
+        let chunk_price = U256::from(10);
+        let data = data(10, 1024, 1);
+        let (seeder, root_hash) =
+            seeder(0, &data, 1024, chunk_price, DefaultSeederPolicy::default()).await;
+        let requester = requester(1, root_hash, &[&seeder], chunk_price).await;
+        assert_eq!(downloaded(&requester, &root_hash).await, data);
+
+        // requester paid for every chunk, as per both
+        let paid = chunk_price * 11;
+        assert!(
+            wait_for(|| seeder.paid(&requester.address, &seeder.address) == paid).await,
+            "seeder was not paid"
+        );
+        assert_eq!(requester.paid(&requester.address, &seeder.address), paid);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn repeated_counter_offers_do_not_lower_price() {
+        // seeder accepts no less than 90% of quoted price,
+        // requester keeps countering just below that
+        let data = data(4, 256, 5);
+        let (seeder, root_hash) = seeder(
+            0,
+            &data,
+            256,
+            U256::from(100),
+            DefaultSeederPolicy::default(),
+        )
+        .await;
+        let mut requester = TestNode::new(1);
+        requester
+            .start(Roles {
+                requester: Some(MaxPricePolicy {
+                    max_counters: 16,
+                    ..requester_policy(U256::from(89))
+                }),
+                downloads: vec![(root_hash, vec![seeder.dial_info()])],
+                ..Default::default()
+            })
+            .await;
+
+        assert!(
+            !wait_for(|| requester.download_path(&root_hash).exists()).await,
+            "seeder accepted counter offer below its floor"
+        );
+        assert!(seeder.paid(&requester.address, &seeder.address).is_zero());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn long_download_is_not_rate_limited() {
+        // with a prepaid window of one chunk, requester
+        // receives an RFP per chunk, well beyond the
+        // burst of default rate limit
+        let chunk_price = U256::from(1);
+        let data = data(200, 64, 7);
+        let (seeder, root_hash) =
+            seeder(0, &data, 64, chunk_price, DefaultSeederPolicy::default()).await;
+        let requester = requester(1, root_hash, &[&seeder], chunk_price).await;
+        assert_eq!(downloaded(&requester, &root_hash).await, data);
+
+        assert!(
+            wait_for(|| seeder.paid(&requester.address, &seeder.address) == chunk_price * 201)
+                .await,
+            "seeder was not paid"
+        );
+        let reputation = seeder
+            .storage
+            .find_reputation(&ReputationKey::Peer(requester.peer_id))
+            .unwrap()
+            .unwrap_or_default();
+        assert_eq!(reputation.unpaid_rfps, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn many_requesters_download_from_one_seeder() {
+        let chunk_price = U256::from(3);
+        let data = data(4, 512, 7);
+        let (seeder, root_hash) =
+            seeder(0, &data, 512, chunk_price, DefaultSeederPolicy::default()).await;
+
+        let mut requesters = Vec::new();
+        for index in 1..4 {
+            requesters.push(requester(index, root_hash, &[&seeder], chunk_price).await);
+        }
+        for requester in &requesters {
+            assert_eq!(downloaded(requester, &root_hash).await, data);
+            assert!(
+                wait_for(|| seeder.paid(&requester.address, &seeder.address) == chunk_price * 5)
+                    .await,
+                "seeder was not paid by {:?}",
+                requester.address
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn file_is_downloaded_from_many_seeders() {
+        let chunk_price = U256::from(2);
+        let data = data(32, 256, 3);
+        let mut seeders = Vec::new();
+        for index in 0..3 {
+            seeders.push(
+                seeder(
+                    index,
+                    &data,
+                    256,
+                    chunk_price,
+                    DefaultSeederPolicy::default(),
+                )
+                .await,
+            );
+        }
+        let root_hash = seeders[0].1;
+        let seeders: Vec<TestNode> = seeders.into_iter().map(|(s, _)| s).collect();
+        let requester = requester(
+            3,
+            root_hash,
+            &seeders.iter().collect::<Vec<_>>(),
+            chunk_price,
+        )
+        .await;
+        assert_eq!(downloaded(&requester, &root_hash).await, data);
+
+        // every chunk was paid for once, and
+        // every seeder sent some of them
+        let total = chunk_price * 33;
+        assert!(
+            wait_for(|| seeders.iter().fold(U256::zero(), |sum, s| sum
+                + s.paid(&requester.address, &s.address))
+                == total)
+            .await,
+            "seeders were not paid"
+        );
+        for seeder in &seeders {
+            assert!(!seeder.paid(&requester.address, &seeder.address).is_zero());
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn node_seeds_and_downloads_at_once() {
+        let chunk_price = U256::from(4);
+        let (seeded, downloaded_data) = (data(6, 256, 11), data(6, 256, 13));
+        let (seeder, downloaded_hash) = seeder(
+            0,
+            &downloaded_data,
+            256,
+            chunk_price,
+            DefaultSeederPolicy::default(),
+        )
+        .await;
+
+        // requests for either role are routed to it
+        let mut node = TestNode::new(1);
+        let seeded_hash = node.add_files(&[&seeded], 256, chunk_price)[0];
+        node.start(Roles {
+            seeder: Some(DefaultSeederPolicy::default()),
+            requester: Some(requester_policy(chunk_price)),
+            downloads: vec![(downloaded_hash, vec![seeder.dial_info()])],
+            ..Default::default()
+        })
+        .await;
+        let requester = requester(2, seeded_hash, &[&node], chunk_price).await;
+
+        assert_eq!(downloaded(&node, &downloaded_hash).await, downloaded_data);
+        assert_eq!(downloaded(&requester, &seeded_hash).await, seeded);
+        let paid = chunk_price * 7;
+        assert!(
+            wait_for(|| seeder.paid(&node.address, &seeder.address) == paid
+                && node.paid(&requester.address, &node.address) == paid)
+            .await,
+            "node was not paid or did not pay"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn invalid_chunks_are_fetched_from_other_seeders() {
+        let chunk_price = U256::from(2);
+        let data = data(32, 256, 9);
+        let (honest, root_hash) =
+            seeder(0, &data, 256, chunk_price, DefaultSeederPolicy::default()).await;
+        let (corrupt, _) = seeder(1, &data, 256, chunk_price, DefaultSeederPolicy::default()).await;
+        // file changes on disk after seeder has started
+        let path = corrupt
+            .dir
+            .path()
+            .join(format!("file-{:x}", H256::from(keccak256(&data))));
+        fs::write(path, data.iter().map(|b| !b).collect::<Vec<_>>()).unwrap();
+
+        let requester = requester(2, root_hash, &[&honest, &corrupt], chunk_price).await;
+        assert_eq!(downloaded(&requester, &root_hash).await, data);
+        let reputation = requester
+            .storage
+            .find_reputation(&ReputationKey::Peer(corrupt.peer_id))
+            .unwrap()
+            .unwrap();
+        assert!(reputation.invalid_chunks > 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn chunks_are_paid_for_after_they_are_sent() {
+        let chunk_price = U256::from(5);
+        let data = data(10, 128, 5);
+        let policy = DefaultSeederPolicy {
+            untrusted_schedule: PaymentSchedule::EveryChunks(4),
+            ..Default::default()
+        };
+        let (seeder, root_hash) = seeder(0, &data, 128, chunk_price, policy).await;
+        let requester = requester(1, root_hash, &[&seeder], chunk_price).await;
+        assert_eq!(downloaded(&requester, &root_hash).await, data);
+
+        // last RFP covers the partial batch
+        let paid = chunk_price * 11;
+        assert!(
+            wait_for(|| seeder.paid(&requester.address, &seeder.address) == paid).await,
+            "seeder was not paid"
+        );
+        assert_eq!(requester.paid(&requester.address, &seeder.address), paid);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn download_resumes_after_requester_restarts() {
+        let chunk_price = U256::from(1);
+        let data = data(64, 128, 11);
+        let mut seeder = TestNode::new(0);
+        let root_hash = seeder.add_files(&[&data], 128, chunk_price)[0];
+        seeder
+            .start(Roles {
+                seeder: Some(DefaultSeederPolicy::default()),
+                bandwidth: THROTTLED,
+                ..Default::default()
+            })
+            .await;
+        let mut requester = requester(1, root_hash, &[&seeder], chunk_price).await;
+
+        // restart in the middle of the transfer
+        assert!(
+            wait_for(|| requester.paid(&requester.address, &seeder.address) >= chunk_price * 8)
+                .await
+        );
+        assert!(requester.paid(&requester.address, &seeder.address) < chunk_price * 64);
+        requester.restart().await;
+
+        assert_eq!(downloaded(&requester, &root_hash).await, data);
+        let paid = chunk_price * 65;
+        assert!(
+            wait_for(|| seeder.paid(&requester.address, &seeder.address) == paid).await,
+            "seeder was not paid"
+        );
+        assert_eq!(requester.paid(&requester.address, &seeder.address), paid);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn corrupted_file_is_fetched_again() {
+        let chunk_price = U256::from(100);
+        let data = data(64, 128, 17);
+        let mut seeder = TestNode::new(0);
+        let root_hash = seeder.add_files(&[&data], 128, chunk_price)[0];
+        seeder
+            .start(Roles {
+                seeder: Some(DefaultSeederPolicy::default()),
+                bandwidth: THROTTLED,
+                ..Default::default()
+            })
+            .await;
+        let requester = requester(1, root_hash, &[&seeder], chunk_price).await;
+
+        // first chunk changes on disk after it was verified
+        let path = requester.download_path(&root_hash);
+        assert!(wait_for(|| fs::metadata(&path).is_ok_and(|m| m.len() >= 8 * 128)).await);
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .write_all_at(&[0; 128], 0)
+            .unwrap();
+
+        // file isn't reported until it matches root hash
+        assert_eq!(downloaded(&requester, &root_hash).await, data);
+        assert!(
+            wait_for(|| seeder.paid(&requester.address, &seeder.address) > chunk_price * 65).await,
+            "file was not fetched again"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn download_resumes_after_seeder_restarts() {
+        let chunk_price = U256::from(1);
+        let data = data(64, 128, 13);
+        let mut seeder = TestNode::new(0);
+        let root_hash = seeder.add_files(&[&data], 128, chunk_price)[0];
+        seeder
+            .start(Roles {
+                seeder: Some(DefaultSeederPolicy::default()),
+                bandwidth: THROTTLED,
+                ..Default::default()
+            })
+            .await;
+        let requester = requester(1, root_hash, &[&seeder], chunk_price).await;
+
+        // restart in the middle of the transfer. Requester
+        // resumes the process once seeder reconnects.
+        assert!(
+            wait_for(|| seeder.paid(&requester.address, &seeder.address) >= chunk_price * 8).await
+        );
+        assert!(seeder.paid(&requester.address, &seeder.address) < chunk_price * 64);
+        seeder.restart().await;
+
+        assert_eq!(downloaded(&requester, &root_hash).await, data);
+        let paid = chunk_price * 65;
+        assert!(
+            wait_for(|| seeder.paid(&requester.address, &seeder.address) == paid).await,
+            "seeder was not paid"
+        );
+        assert_eq!(requester.paid(&requester.address, &seeder.address), paid);
+    }
+}
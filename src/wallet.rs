@@ -1,9 +1,54 @@
 use super::storage::Storage;
-use ethers::types::{Address, Signature, U256};
+use ethers::{
+    signers::{LocalWallet, Signer},
+    types::{Address, Signature, U256},
+    utils::hash_message,
+};
+use libp2p::PeerId;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Prefix of the message signed in an attestation, so
+/// that the signature can't be replayed elsewhere
+const ATTESTATION_PREFIX: &[u8] = b"dse-peer-attestation:";
+
+/// Binds `peer_id` to `address`, signed
+/// with the key of `address`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Attestation {
+    pub peer_id: PeerId,
+    pub address: Address,
+    signature: Signature,
+}
+
+impl Attestation {
+    fn message(peer_id: &PeerId) -> Vec<u8> {
+        [ATTESTATION_PREFIX, &peer_id.to_bytes()].concat()
+    }
+
+    pub fn new(signer: &LocalWallet, peer_id: PeerId) -> Self {
+        let signature = signer.sign_hash(hash_message(Self::message(&peer_id)), false);
+        Self {
+            peer_id,
+            address: signer.address(),
+            signature,
+        }
+    }
+
+    /// Verifies that attestation is signed by its address
+    /// and binds `peer_id`. Returns the attested address.
+    pub fn verify(&self, peer_id: &PeerId) -> anyhow::Result<Address> {
+        if self.peer_id != *peer_id {
+            return Err(anyhow::anyhow!("Attestation is for another peer"));
+        }
+        self.signature
+            .verify(Self::message(peer_id), self.address)
+            .map_err(|e| anyhow::anyhow!("Invalid attestation: {}", e))?;
+        Ok(self.address)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Receipt {
     a_address: Address,
     b_address: Address,
@@ -12,6 +57,33 @@ pub struct Receipt {
     expires_by: U256,
 }
 
+impl Receipt {
+    /// Empty receipt between `x` & `y`. Lower address
+    /// is `a_address`, so that both users derive the
+    /// same receipt independently.
+    fn new(x: Address, y: Address) -> Self {
+        let (a_address, b_address) = if x < y { (x, y) } else { (y, x) };
+        Self {
+            a_address,
+            b_address,
+            a_owes: U256::zero(),
+            b_owes: U256::zero(),
+            expires_by: U256::zero(),
+        }
+    }
+
+    /// Amount `address` owes as per the receipt
+    pub fn owes(&self, address: &Address) -> U256 {
+        if *address == self.a_address {
+            self.a_owes
+        } else if *address == self.b_address {
+            self.b_owes
+        } else {
+            U256::zero()
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 enum Status {
     Active,
@@ -31,6 +103,10 @@ pub struct ReceiptWithSignatures {
 }
 
 impl ReceiptWithSignatures {
+    pub fn receipt(&self) -> &Receipt {
+        &self.receipt
+    }
+
     fn validate_signatures(&self) -> bool {
         todo!();
         true
@@ -58,9 +134,31 @@ pub struct Wallet {
     total_balance: U256,
     //TODO: Shift this to somewhere appropriate
     self_address: Address,
+    /// Key of `self_address`
+    signer: LocalWallet,
 }
 
 impl Wallet {
+    /// Wallet of `signer` with `balance` available
+    /// for paying other users
+    pub fn new(storage: Storage, signer: LocalWallet, balance: U256) -> Self {
+        Self {
+            storage,
+            balance_owes: HashMap::new(),
+            balance_owed: HashMap::new(),
+            total_owes: U256::zero(),
+            total_owed: U256::zero(),
+            total_balance: balance,
+            self_address: signer.address(),
+            signer,
+        }
+    }
+
+    /// Attestation binding `peer_id` to self address
+    pub fn attest(&self, peer_id: PeerId) -> Attestation {
+        Attestation::new(&self.signer, peer_id)
+    }
+
     pub fn can_pay(&self, amount: U256) -> bool {
         self.total_balance.gt(&(self.total_owes + amount))
     }
@@ -76,21 +174,12 @@ impl Wallet {
         let mut receipt = {
             match self.storage.find_active_receipt(&user) {
                 Ok(r) => r,
-                Err(_) => {
-                    ReceiptWithSignatures {
-                        // TODO - who decides the order a_address & b_address
-                        receipt: Receipt {
-                            a_address: user,
-                            b_address: self.self_address,
-                            a_owes: U256::zero(),
-                            b_owes: U256::zero(),
-                            expires_by: U256::zero(),
-                        },
-                        a_signature: None,
-                        b_signature: None,
-                        status: Status::Active,
-                    }
-                }
+                Err(_) => ReceiptWithSignatures {
+                    receipt: Receipt::new(user, self.self_address),
+                    a_signature: None,
+                    b_signature: None,
+                    status: Status::Active,
+                },
             }
         };
 
@@ -117,21 +206,12 @@ impl Wallet {
         let old_receipt = {
             match self.storage.find_active_receipt(&user) {
                 Ok(r) => r,
-                Err(_) => {
-                    ReceiptWithSignatures {
-                        // TODO - who decides the order a_address & b_address
-                        receipt: Receipt {
-                            a_address: user,
-                            b_address: self.self_address,
-                            a_owes: U256::zero(),
-                            b_owes: U256::zero(),
-                            expires_by: U256::zero(),
-                        },
-                        a_signature: None,
-                        b_signature: None,
-                        status: Status::Active,
-                    }
-                }
+                Err(_) => ReceiptWithSignatures {
+                    receipt: Receipt::new(user, self.self_address),
+                    a_signature: None,
+                    b_signature: None,
+                    status: Status::Active,
+                },
             }
         };
 
@@ -172,3 +252,37 @@ impl Wallet {
 
 // Unrelated
 // Think about to roll up receipts and post them onchain
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signer() -> LocalWallet {
+        "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn attestation_binds_peer_id_to_address() {
+        let signer = signer();
+        let peer_id = PeerId::random();
+        let attestation = Attestation::new(&signer, peer_id);
+
+        assert_eq!(attestation.verify(&peer_id).unwrap(), signer.address());
+        assert!(attestation.verify(&PeerId::random()).is_err());
+
+        // claiming another address with the same signature fails
+        let forged = Attestation {
+            address: Address::random(),
+            ..attestation
+        };
+        assert!(forged.verify(&peer_id).is_err());
+    }
+
+    #[test]
+    fn both_users_derive_same_receipt() {
+        let (x, y) = (Address::random(), Address::random());
+        assert_eq!(Receipt::new(x, y), Receipt::new(y, x));
+    }
+}